@@ -16,58 +16,73 @@ layout(set = 1, binding = 2) uniform sampler ColorMaterial_texture_sampler;
 // } Tilemap;
 
 #define MAP_SAMPLER (usampler2D(ColorMaterial_texture, ColorMaterial_texture_sampler))
-#define MATERIAL_AT_OFFSET(x, y) (textureOffset(MAP_SAMPLER, v_Uv, ivec2((x), (y))).r)
+#define LOOK_AT_OFFSET(x, y) (textureOffset(MAP_SAMPLER, v_Uv, ivec2((x), (y))).a)
 
-void main() {
-    vec4 color;
-    uint material = texture(MAP_SAMPLER, v_Uv).x;
-    switch (material) {
-        case 0: color = vec4(1, 1, 1, 0.01); break;
-        case 1:
-            color = vec4(0.5, 0.5, 0.5, 1.0);
+// Must match `Look` in tilemap.rs
+#define LOOK_EMPTY 0
+#define LOOK_SOLID 1
+#define LOOK_LIQUID 2
+#define LOOK_POWDER 3
+#define LOOK_GAS 4
+#define LOOK_HEAT 5
+#define LOOK_SPARK 6
 
+void main() {
+    uvec4 tile = texture(MAP_SAMPLER, v_Uv);
+    vec4 color = vec4(vec3(tile.rgb) / 255.0, 1.0);
+    switch (tile.a) {
+        case LOOK_EMPTY: color.a = 0.01; break;
+        case LOOK_SOLID:
             // arbitrary sampling for semi-random looking results
             vec4 darken = vec4(0.04, 0.04, 0.04, 0);
-            if (MATERIAL_AT_OFFSET(-1,  0) != 0) color -= darken;
-            if (MATERIAL_AT_OFFSET( 1,  0) != 0) color -= darken;
-            if (MATERIAL_AT_OFFSET( 0, -1) != 0) color -= darken;
-            if (MATERIAL_AT_OFFSET( 0,  1) != 0) color -= darken;
-            if (MATERIAL_AT_OFFSET(-2,  2) != 0) color -= darken;
-            if (MATERIAL_AT_OFFSET( 2, -2) != 0) color -= darken;
-            if (MATERIAL_AT_OFFSET( 2, -2) != 0) color -= darken;
-            if (MATERIAL_AT_OFFSET(-2,  2) != 0) color -= darken;
-            if (MATERIAL_AT_OFFSET(-3,  0) != 0) color -= darken;
-            if (MATERIAL_AT_OFFSET( 0, -3) != 0) color -= darken;
-            if (MATERIAL_AT_OFFSET( 0, -3) != 0) color -= darken;
-            if (MATERIAL_AT_OFFSET(-3,  0) != 0) color -= darken;
+            if (LOOK_AT_OFFSET(-1,  0) != LOOK_EMPTY) color -= darken;
+            if (LOOK_AT_OFFSET( 1,  0) != LOOK_EMPTY) color -= darken;
+            if (LOOK_AT_OFFSET( 0, -1) != LOOK_EMPTY) color -= darken;
+            if (LOOK_AT_OFFSET( 0,  1) != LOOK_EMPTY) color -= darken;
+            if (LOOK_AT_OFFSET(-2,  2) != LOOK_EMPTY) color -= darken;
+            if (LOOK_AT_OFFSET( 2, -2) != LOOK_EMPTY) color -= darken;
+            if (LOOK_AT_OFFSET( 2, -2) != LOOK_EMPTY) color -= darken;
+            if (LOOK_AT_OFFSET(-2,  2) != LOOK_EMPTY) color -= darken;
+            if (LOOK_AT_OFFSET(-3,  0) != LOOK_EMPTY) color -= darken;
+            if (LOOK_AT_OFFSET( 0, -3) != LOOK_EMPTY) color -= darken;
+            if (LOOK_AT_OFFSET( 0, -3) != LOOK_EMPTY) color -= darken;
+            if (LOOK_AT_OFFSET(-3,  0) != LOOK_EMPTY) color -= darken;
             break;
-        case 2:
-            color = vec4(0.0, 0.1, 1.0, 0.8);
+        case LOOK_LIQUID:
+            color.a = 0.8;
 
-            // darken the water when space above is occupied
+            // darken the liquid when space above is occupied
             vec4 shadow = vec4(0, 0.05, 0.05, 0);
-            if (MATERIAL_AT_OFFSET(0, -1) == 0) color += shadow;
-            if (MATERIAL_AT_OFFSET(0, -2) == 0) color += shadow;
-            if (MATERIAL_AT_OFFSET(0, -3) == 0) color += shadow;
-            if (MATERIAL_AT_OFFSET(0, -4) == 0) color += shadow;
-            if (MATERIAL_AT_OFFSET(0, -5) == 0) color += shadow;
-            if (MATERIAL_AT_OFFSET(0, -6) == 0) color += shadow;
-            if (MATERIAL_AT_OFFSET(0, -7) == 0) color += shadow;
-            if (MATERIAL_AT_OFFSET(0, -8) == 0) color += shadow;
+            if (LOOK_AT_OFFSET(0, -1) == LOOK_EMPTY) color += shadow;
+            if (LOOK_AT_OFFSET(0, -2) == LOOK_EMPTY) color += shadow;
+            if (LOOK_AT_OFFSET(0, -3) == LOOK_EMPTY) color += shadow;
+            if (LOOK_AT_OFFSET(0, -4) == LOOK_EMPTY) color += shadow;
+            if (LOOK_AT_OFFSET(0, -5) == LOOK_EMPTY) color += shadow;
+            if (LOOK_AT_OFFSET(0, -6) == LOOK_EMPTY) color += shadow;
+            if (LOOK_AT_OFFSET(0, -7) == LOOK_EMPTY) color += shadow;
+            if (LOOK_AT_OFFSET(0, -8) == LOOK_EMPTY) color += shadow;
             break;
-        case 3:
-            color = vec4(0.5, 0.5, 0.0, 1.0);
-
-            // darken the sand when near water
-            vec4 wetness = vec4(0.1, 0.1, 0.05, 0);
-            if (MATERIAL_AT_OFFSET(-2,  0) == 2) color -= wetness;
-            if (MATERIAL_AT_OFFSET(-1,  0) == 2) color -= wetness;
-            if (MATERIAL_AT_OFFSET( 1,  0) == 2) color -= wetness;
-            if (MATERIAL_AT_OFFSET( 2,  0) == 2) color -= wetness;
-            if (MATERIAL_AT_OFFSET( 0, -1) == 2) color -= wetness;
-            if (MATERIAL_AT_OFFSET( 0,  1) == 2) color -= wetness;
+        case LOOK_POWDER:
+            // wet sand has its own colour, so powders are drawn as they are
+            break;
+        case LOOK_GAS: color.a = 0.5; break;
+        case LOOK_HEAT:
+            // see `Material::heat` for the encoding
+            float temperature = float(tile.r) * 5.0 - 50.0;
+            vec3 cold = vec3(0.0, 0.3, 1.0);
+            vec3 ambient = vec3(0.1, 0.1, 0.1);
+            vec3 warm = vec3(1.0, 0.0, 0.0);
+            vec3 hot = vec3(1.0, 1.0, 0.0);
+            vec3 blazing = vec3(1.0, 1.0, 1.0);
+            if (temperature < 20.0) color.rgb = mix(cold, ambient, (temperature + 50.0) / 70.0);
+            else if (temperature < 100.0) color.rgb = mix(ambient, warm, (temperature - 20.0) / 80.0);
+            else if (temperature < 500.0) color.rgb = mix(warm, hot, (temperature - 100.0) / 400.0);
+            else color.rgb = mix(hot, blazing, clamp((temperature - 500.0) / 700.0, 0.0, 1.0));
+            break;
+        case LOOK_SPARK:
+            color.rgb = mix(color.rgb, vec3(1.0, 1.0, 0.6), 0.8);
             break;
-        default: color = vec4(1, 0, 1, 1); // unknown material
+        default: color = vec4(1, 0, 1, 1); // unknown look
     }
 
     vec2 inner = v_Uv;
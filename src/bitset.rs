@@ -0,0 +1,78 @@
+use std::ops::Range;
+
+#[derive(Debug, Clone, Default)]
+pub struct BitSet {
+    words: Box<[u64]>,
+}
+
+impl BitSet {
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)].into_boxed_slice(),
+        }
+    }
+
+    pub fn full(len: usize) -> Self {
+        let mut set = Self::new(len);
+        for index in 0..len {
+            set.insert(index);
+        }
+        set
+    }
+
+    pub fn insert(&mut self, index: usize) {
+        self.words[index / 64] |= 1 << (index % 64);
+    }
+
+    /// Inserts every index in `range`, setting the bits of each word at once.
+    pub fn insert_range(&mut self, range: Range<usize>) {
+        let mut start = range.start;
+
+        while start < range.end {
+            let end = range.end.min((start / 64 + 1) * 64);
+            let bits = !0 >> (64 - (end - start)) << (start % 64);
+
+            self.words[start / 64] |= bits;
+            start = end;
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        self.words[index / 64] &= !(1 << (index % 64));
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.words[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    pub fn clear(&mut self) {
+        for word in self.words.iter_mut() {
+            *word = 0;
+        }
+    }
+
+    /// Iterates over the indices in the set in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words
+            .iter()
+            .enumerate()
+            .filter(|(_, &word)| word != 0)
+            .flat_map(|(offset, &word)| {
+                let mut word = word;
+
+                std::iter::from_fn(move || {
+                    if word == 0 {
+                        return None;
+                    }
+
+                    let bit = word.trailing_zeros() as usize;
+                    word &= word - 1;
+                    Some(offset * 64 + bit)
+                })
+            })
+    }
+}
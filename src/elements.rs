@@ -0,0 +1,487 @@
+use std::{error::Error, fmt};
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Element {
+    Air,
+    Rock,
+    Water,
+    Sand(u8),
+    /// Burns for the element's `lifetime` in ticks, counting up from zero.
+    Fire(u8),
+    /// Dissipates into air once its age reaches the element's `lifetime`.
+    Smoke(u8),
+    /// Condenses back into water once its age reaches the element's `lifetime`.
+    Steam(u8),
+    Lava,
+    Obsidian,
+    /// Dissolves its neighbours, counting how many it has dissolved up from zero until it is
+    /// neutralised.
+    Acid(u8),
+    /// Sand that soaked up water, with the same support counter as [`Element::Sand`] followed by
+    /// how long it has been drying in the air. It dries back into sand once that reaches the
+    /// element's `lifetime`.
+    WetSand(u8, u8),
+    /// Sprouts into a plant once it rests on sand next to water.
+    Seed,
+    /// Grows using the water it draws up, keeping track of how many cells of plant it is away
+    /// from water.
+    Plant(u8),
+    /// Cannot be destroyed, and holds up rock like the edges of the map.
+    Wall,
+    /// Sparks the conductive cells next to it, then disappears.
+    Spark,
+    /// An element without built-in behaviour, identified by its index in the [`ElementTable`].
+    Custom(u8),
+}
+
+impl Element {
+    /// Whether both are the same element, whatever state they carry.
+    pub fn same_as(self, other: Element) -> bool {
+        match (self, other) {
+            (Element::Custom(a), Element::Custom(b)) => a == b,
+            _ => std::mem::discriminant(&self) == std::mem::discriminant(&other),
+        }
+    }
+
+    /// Position of a built-in element in [`BUILTINS`].
+    fn slot(self) -> usize {
+        match self {
+            Element::Air => 0,
+            Element::Rock => 1,
+            Element::Water => 2,
+            Element::Sand(_) => 3,
+            Element::Fire(_) => 4,
+            Element::Smoke(_) => 5,
+            Element::Steam(_) => 6,
+            Element::Lava => 7,
+            Element::Obsidian => 8,
+            Element::Acid(_) => 9,
+            Element::WetSand(..) => 10,
+            Element::Seed => 11,
+            Element::Plant(_) => 12,
+            Element::Wall => 13,
+            Element::Spark => 14,
+            Element::Custom(_) => unreachable!("custom elements are not built in"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum State {
+    Solid,
+    Powder,
+    Liquid,
+    Gas,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Definition {
+    pub name: String,
+    #[serde(default)]
+    pub hotkey: Option<char>,
+    pub colour: (f32, f32, f32),
+    pub density: f32,
+    pub state: State,
+    /// Chance per tick, from 0 to 1, that a liquid holds still instead of spreading sideways.
+    #[serde(default)]
+    pub viscosity: f32,
+    /// How many cells a liquid can spread sideways in a single tick.
+    #[serde(default = "Definition::default_dispersion")]
+    pub dispersion: u8,
+    /// Chance per tick that an adjacent fire or lava sets this element on fire.
+    #[serde(default)]
+    pub flammability: f32,
+    /// Radius, in cells, of the explosion when this element is set on fire or heated into fire.
+    /// Zero means it just burns.
+    #[serde(default)]
+    pub blast_radius: u8,
+    /// Fraction of the temperature difference with a neighbour that is evened out per tick, from
+    /// 0 to 1. Heat flows between two cells at the lower of their conductivities.
+    #[serde(default)]
+    pub conductivity: f32,
+    /// Temperature, in °C, of newly placed cells. Fire also stays at this temperature while
+    /// it burns.
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// Whether the element keeps its `temperature` however much heat flows in or out of it,
+    /// heating or cooling its surroundings indefinitely.
+    #[serde(default)]
+    pub holds_temperature: bool,
+    #[serde(default)]
+    pub cools_into: Option<PhaseChange>,
+    #[serde(default)]
+    pub heats_into: Option<PhaseChange>,
+    /// What happens when the element is next to certain others.
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    /// How many ticks fire, smoke and steam last before turning into something else, and wet
+    /// sand lies in the air before drying. Zero means they last forever.
+    #[serde(default)]
+    pub lifetime: u8,
+    /// Chance per tick that acid dissolves a neighbour, divided by how strongly the neighbour
+    /// holds together.
+    #[serde(default)]
+    pub corrosiveness: f32,
+    /// How many cells acid dissolves before it is neutralised into water.
+    #[serde(default)]
+    pub potency: u8,
+    /// Velocity, in cells per tick, that a solid blows into the powders and liquids in front of
+    /// it, in the direction it points.
+    #[serde(default)]
+    pub wind: (f32, f32),
+    /// Element that a solid keeps pouring into the empty cells next to it, like a tap.
+    #[serde(default)]
+    pub emits: Option<Emission>,
+    /// Whether a solid removes every powder, liquid and gas that touches it.
+    #[serde(default)]
+    pub drains: bool,
+    /// Whether sparks travel through the element.
+    #[serde(default)]
+    pub conducts_electricity: bool,
+    /// Whether a solid keeps sparking the conductive cells next to it, like a battery.
+    #[serde(default)]
+    pub powers: bool,
+    /// Steepest slope, in degrees, a pile of this powder can hold.
+    #[serde(default = "Definition::default_angle_of_repose")]
+    pub angle_of_repose: f32,
+}
+
+impl Definition {
+    fn default_dispersion() -> u8 {
+        1
+    }
+
+    fn default_angle_of_repose() -> f32 {
+        45.0
+    }
+
+    pub fn rgb(&self) -> [u8; 3] {
+        let (r, g, b) = self.colour;
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+        [channel(r), channel(g), channel(b)]
+    }
+}
+
+/// Turns an element into another one past a temperature: below it for `cools_into`, above it
+/// for `heats_into`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PhaseChange {
+    pub temperature: f32,
+    pub element: String,
+    /// Heat, in °C, that has to flow out of or into the element past the temperature before it
+    /// changes, and that the change then gives back. It keeps ice from melting as soon as it
+    /// gets warm, and keeps the water it melts into from freezing right back.
+    #[serde(default)]
+    pub latent_heat: f32,
+}
+
+/// A [`PhaseChange`] with the element name resolved.
+#[derive(Debug, Clone, Copy)]
+pub struct Transition {
+    pub temperature: f32,
+    pub latent_heat: f32,
+    pub element: Element,
+}
+
+/// The phase changes of a [`Definition`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Phases {
+    pub cools_into: Option<Transition>,
+    pub heats_into: Option<Transition>,
+}
+
+/// How far a grain of powder slides down its pile, derived from the angle of repose: steep
+/// slopes require a drop of several cells next to the grain, shallow ones let it travel several
+/// cells sideways before dropping by one.
+#[derive(Debug, Clone, Copy)]
+pub struct Repose {
+    /// Cells the grain travels sideways.
+    pub run: f32,
+    /// Cells the ground at the end of the run has to be lower than the grain.
+    pub drop: f32,
+    /// Support below which a grain stays put without trying to slide, kept low enough that
+    /// grains at the foot of a slope steeper than the angle of repose still do.
+    pub cohesion: u8,
+}
+
+impl Repose {
+    fn new(angle_of_repose: f32) -> Self {
+        let slope = angle_of_repose.to_radians().tan().max(0.1);
+        let (run, drop) = if slope >= 1.0 {
+            (1.0, slope)
+        } else {
+            (1.0 / slope, 1.0)
+        };
+
+        Repose {
+            run,
+            drop,
+            cohesion: drop.ceil() as u8 + 1,
+        }
+    }
+}
+
+/// Has a `chance` per tick of placing `element` next to the emitting element.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Emission {
+    pub element: String,
+    pub chance: f32,
+}
+
+/// An [`Emission`] with the element name resolved.
+#[derive(Debug, Clone, Copy)]
+pub struct Emitter {
+    pub element: Element,
+    pub chance: f32,
+}
+
+/// While next to `neighbour`, has a `chance` per tick of turning into `into` and turning the
+/// neighbour into `neighbour_into`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Reaction {
+    pub neighbour: String,
+    pub into: String,
+    pub neighbour_into: String,
+    pub chance: f32,
+}
+
+/// A [`Reaction`] with the element names resolved.
+#[derive(Debug, Clone, Copy)]
+pub struct Reactants {
+    pub neighbour: Element,
+    pub into: Element,
+    pub neighbour_into: Element,
+    pub chance: f32,
+}
+
+/// Elements with built-in behaviour, in the order given by [`Element::slot`].
+const BUILTINS: &[(Element, &str)] = &[
+    (Element::Air, "Air"),
+    (Element::Rock, "Rock"),
+    (Element::Water, "Water"),
+    (Element::Sand(0), "Sand"),
+    (Element::Fire(0), "Fire"),
+    (Element::Smoke(0), "Smoke"),
+    (Element::Steam(0), "Steam"),
+    (Element::Lava, "Lava"),
+    (Element::Obsidian, "Obsidian"),
+    (Element::Acid(0), "Acid"),
+    (Element::WetSand(0, 0), "Wet Sand"),
+    (Element::Seed, "Seed"),
+    (Element::Plant(0), "Plant"),
+    (Element::Wall, "Wall"),
+    (Element::Spark, "Spark"),
+];
+
+#[derive(Debug, Clone)]
+pub struct ElementTable {
+    definitions: Vec<Definition>,
+    builtins: [usize; BUILTINS.len()],
+    phases: Vec<Phases>,
+    reactions: Vec<Vec<Reactants>>,
+    emitters: Vec<Option<Emitter>>,
+    repose: Vec<Repose>,
+}
+
+impl ElementTable {
+    pub fn parse(source: &[u8]) -> Result<Self, TableError> {
+        let definitions: Vec<Definition> = ron::de::from_bytes(source)?;
+
+        if definitions.len() > u8::MAX as usize + 1 {
+            return Err(TableError::TooMany(definitions.len()));
+        }
+
+        let mut builtins = [0; BUILTINS.len()];
+
+        for (builtin, &(_, name)) in builtins.iter_mut().zip(BUILTINS) {
+            *builtin = definitions
+                .iter()
+                .position(|definition| definition.name == name)
+                .ok_or(TableError::Missing(name))?;
+        }
+
+        let mut table = Self {
+            definitions,
+            builtins,
+            phases: Vec::new(),
+            reactions: Vec::new(),
+            emitters: Vec::new(),
+            repose: Vec::new(),
+        };
+
+        table.phases = table
+            .definitions
+            .iter()
+            .map(|definition| {
+                let resolve = |change: &Option<PhaseChange>| {
+                    change
+                        .as_ref()
+                        .map(|change| {
+                            table.resolve(&change.element).map(|element| Transition {
+                                temperature: change.temperature,
+                                latent_heat: change.latent_heat,
+                                element,
+                            })
+                        })
+                        .transpose()
+                };
+
+                Ok(Phases {
+                    cools_into: resolve(&definition.cools_into)?,
+                    heats_into: resolve(&definition.heats_into)?,
+                })
+            })
+            .collect::<Result<_, TableError>>()?;
+
+        table.reactions = table
+            .definitions
+            .iter()
+            .map(|definition| {
+                definition
+                    .reactions
+                    .iter()
+                    .map(|reaction| {
+                        Ok(Reactants {
+                            neighbour: table.resolve(&reaction.neighbour)?,
+                            into: table.resolve(&reaction.into)?,
+                            neighbour_into: table.resolve(&reaction.neighbour_into)?,
+                            chance: reaction.chance,
+                        })
+                    })
+                    .collect()
+            })
+            .collect::<Result<_, TableError>>()?;
+
+        table.emitters = table
+            .definitions
+            .iter()
+            .map(|definition| {
+                definition
+                    .emits
+                    .as_ref()
+                    .map(|emission| {
+                        table.resolve(&emission.element).map(|element| Emitter {
+                            element,
+                            chance: emission.chance,
+                        })
+                    })
+                    .transpose()
+            })
+            .collect::<Result<_, TableError>>()?;
+
+        table.repose = table
+            .definitions
+            .iter()
+            .map(|definition| Repose::new(definition.angle_of_repose))
+            .collect();
+
+        Ok(table)
+    }
+
+    fn resolve(&self, name: &str) -> Result<Element, TableError> {
+        self.find(name)
+            .ok_or_else(|| TableError::Unknown(name.to_owned()))
+    }
+
+    /// Custom elements left over from a previous version of the table are treated as air.
+    pub fn get(&self, element: Element) -> &Definition {
+        self.definitions
+            .get(self.index(element))
+            .unwrap_or_else(|| &self.definitions[self.builtins[Element::Air.slot()]])
+    }
+
+    pub fn phases(&self, element: Element) -> Phases {
+        self.phases
+            .get(self.index(element))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn reactions(&self, element: Element) -> &[Reactants] {
+        self.reactions
+            .get(self.index(element))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    pub fn emitter(&self, element: Element) -> Option<Emitter> {
+        self.emitters.get(self.index(element)).copied().flatten()
+    }
+
+    pub fn repose(&self, element: Element) -> Repose {
+        self.repose
+            .get(self.index(element))
+            .copied()
+            .unwrap_or_else(|| Repose::new(Definition::default_angle_of_repose()))
+    }
+
+    pub fn find(&self, name: &str) -> Option<Element> {
+        self.definitions
+            .iter()
+            .position(|definition| definition.name == name)
+            .map(|index| self.element(index))
+    }
+
+    /// Every defined element, in the order they appear in the definition file.
+    pub fn iter(&self) -> impl Iterator<Item = (Element, &Definition)> + '_ {
+        self.definitions
+            .iter()
+            .enumerate()
+            .map(move |(index, definition)| (self.element(index), definition))
+    }
+
+    fn element(&self, index: usize) -> Element {
+        match self.builtins.iter().position(|&builtin| builtin == index) {
+            Some(slot) => BUILTINS[slot].0,
+            None => Element::Custom(index as u8),
+        }
+    }
+
+    /// Position of the element's definition, as yielded by [`ElementTable::iter`].
+    pub fn index(&self, element: Element) -> usize {
+        match element {
+            Element::Custom(index) => index as usize,
+            builtin => self.builtins[builtin.slot()],
+        }
+    }
+}
+
+impl Default for ElementTable {
+    fn default() -> Self {
+        Self::parse(include_bytes!("../assets/elements.ron")).unwrap()
+    }
+}
+
+#[derive(Debug)]
+pub enum TableError {
+    Syntax(ron::Error),
+    Missing(&'static str),
+    Unknown(String),
+    TooMany(usize),
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TableError::Syntax(error) => write!(f, "{}", error),
+            TableError::Missing(name) => write!(f, "built-in element {} is not defined", name),
+            TableError::Unknown(name) => write!(f, "element {} is not defined", name),
+            TableError::TooMany(count) => write!(
+                f,
+                "{} elements are defined, but at most {} are supported",
+                count,
+                u8::MAX as usize + 1
+            ),
+        }
+    }
+}
+
+impl Error for TableError {}
+
+impl From<ron::Error> for TableError {
+    fn from(error: ron::Error) -> Self {
+        TableError::Syntax(error)
+    }
+}
@@ -1,27 +1,67 @@
+mod bitset;
+mod elements;
+mod scene;
+mod simulation;
 mod tilemap;
 
-use std::{cmp::Ordering, ops::Range};
+use std::{cmp::Ordering, ops::Range, time::Instant};
 
 use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
     input::{keyboard::KeyboardInput, mouse::MouseWheel, ElementState},
     math::Vec3Swizzles,
     prelude::*,
+    reflect::TypeUuid,
     render::camera::WindowOrigin,
+    utils::BoxedFuture,
 };
-use rand::Rng;
-use tilemap::{
-    DownNeighbour, LeftNeighbour, Material, RightNeighbour, TilePosition, Tilemap, TilemapPlugin,
-    UpNeighbour,
-};
+use elements::{Element, ElementTable, State};
+use simulation::{Border, Borders, Simulation};
+use tilemap::{Look, Material, Tilemap, TilemapPlugin};
 
 const WINDOW_WIDTH: f32 = 1280.0;
 const WINDOW_HEIGHT: f32 = 720.0;
 const DOT_SIZE: usize = 8;
 const BRUSH_SIZE: Range<usize> = 0..4;
-const PALETTE: &'static [(Element, &'static str, KeyCode)] = &[
-    (Element::Rock, "Rock", KeyCode::R),
-    (Element::Water, "Water", KeyCode::W),
-    (Element::Sand(0), "Sand", KeyCode::S),
+const SCENE_PATH: &str = "scene.ldirt";
+const IMAGE_PATH: &str = "scene.png";
+const HOTKEYS: &[(char, KeyCode)] = &[
+    ('A', KeyCode::A),
+    ('B', KeyCode::B),
+    ('C', KeyCode::C),
+    ('D', KeyCode::D),
+    ('E', KeyCode::E),
+    ('F', KeyCode::F),
+    ('G', KeyCode::G),
+    ('H', KeyCode::H),
+    ('I', KeyCode::I),
+    ('J', KeyCode::J),
+    ('K', KeyCode::K),
+    ('L', KeyCode::L),
+    ('M', KeyCode::M),
+    ('N', KeyCode::N),
+    ('O', KeyCode::O),
+    ('P', KeyCode::P),
+    ('Q', KeyCode::Q),
+    ('R', KeyCode::R),
+    ('S', KeyCode::S),
+    ('T', KeyCode::T),
+    ('U', KeyCode::U),
+    ('V', KeyCode::V),
+    ('W', KeyCode::W),
+    ('X', KeyCode::X),
+    ('Y', KeyCode::Y),
+    ('Z', KeyCode::Z),
+    ('1', KeyCode::Key1),
+    ('2', KeyCode::Key2),
+    ('3', KeyCode::Key3),
+    ('4', KeyCode::Key4),
+    ('5', KeyCode::Key5),
+    ('6', KeyCode::Key6),
+    ('7', KeyCode::Key7),
+    ('8', KeyCode::Key8),
+    ('9', KeyCode::Key9),
+    ('0', KeyCode::Key0),
 ];
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, StageLabel)]
@@ -31,14 +71,6 @@ enum GameStage {
     Tally,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Element {
-    Air,
-    Rock,
-    Water,
-    Sand(u8),
-}
-
 #[derive(Debug)]
 struct Brush {
     size: usize,
@@ -57,8 +89,25 @@ struct PaletteItem {
     hotkey: KeyCode,
 }
 
+#[derive(Debug, TypeUuid)]
+#[uuid = "5d3a8f3e-1b7c-4b8e-9f0a-6c2d4e8b1a97"]
+struct ElementsAsset(ElementTable);
+
+#[derive(Debug, Default)]
+struct ElementsLoader;
+
+struct ElementsFile(Handle<ElementsAsset>);
+
+/// Whether the map shows the temperature of each cell instead of its element.
+#[derive(Debug, Default)]
+struct HeatOverlay(bool);
+
+struct PaletteBar;
+
 struct BrushSlider;
 
+struct SeedLabel;
+
 struct ViewCamera;
 
 struct TutorialWindow;
@@ -72,6 +121,29 @@ fn main() {
         (fit(WINDOW_WIDTH), fit(WINDOW_HEIGHT))
     };
 
+    let seed = std::env::args()
+        .skip_while(|arg| arg != "--seed")
+        .nth(1)
+        .map(|seed| seed.parse().expect("--seed must be an unsigned integer"))
+        .unwrap_or_else(rand::random);
+
+    let borders = std::env::args()
+        .skip_while(|arg| arg != "--borders")
+        .nth(1)
+        .map(|borders| {
+            parse_borders(&borders)
+                .expect("--borders must be four of solid, void or wrap, separated by commas")
+        })
+        .unwrap_or_default();
+
+    if std::env::args().any(|arg| arg == "--bench") {
+        benchmark(seed);
+        return;
+    }
+
+    let mut simulation = Simulation::new(width, height, Element::Air, seed);
+    simulation.set_borders(borders);
+
     App::build()
         .insert_resource(WindowDescriptor {
             width: WINDOW_WIDTH,
@@ -81,10 +153,12 @@ fn main() {
             ..Default::default()
         })
         .insert_resource(ClearColor(Color::BLACK))
+        .insert_resource(simulation)
         .insert_resource(Brush {
             size: 1,
-            paint: PALETTE[0].0,
+            paint: Element::Rock,
         })
+        .insert_resource(HeatOverlay::default())
         .insert_resource(TutorialTimer {
             show: Timer::from_seconds(5.0, false),
             animate: Timer::from_seconds(0.5, false),
@@ -97,16 +171,16 @@ fn main() {
         .add_stage_after(GameStage::Interact, GameStage::Run, SystemStage::parallel())
         .add_stage_after(GameStage::Run, GameStage::Tally, SystemStage::parallel())
         .add_plugins(DefaultPlugins)
-        .add_plugin(TilemapPlugin::<Element>::new(
-            width,
-            height,
-            DOT_SIZE as f32,
-            Element::Air,
-        ))
+        .add_plugin(TilemapPlugin::new(width, height, DOT_SIZE as f32))
+        .add_asset::<ElementsAsset>()
+        .init_asset_loader::<ElementsLoader>()
         .add_startup_system(init.system())
         .add_system_to_stage(GameStage::Interact, change_element.system())
         .add_system_to_stage(GameStage::Interact, brush.system())
-        .add_system_to_stage(GameStage::Run, rules.system())
+        .add_system_to_stage(GameStage::Interact, scenes.system())
+        .add_system_to_stage(GameStage::Interact, overlay.system())
+        .add_system_to_stage(GameStage::Interact, reload_elements.system())
+        .add_system_to_stage(GameStage::Run, simulate.system())
         .add_system_to_stage(GameStage::Run, update_visuals.system())
         .add_system_to_stage(GameStage::Run, tutorial.system())
         .run();
@@ -116,6 +190,7 @@ fn init(
     mut commands: Commands,
     mut materials: ResMut<Assets<ColorMaterial>>,
     asset_server: Res<AssetServer>,
+    simulation: Res<Simulation>,
 ) {
     let mut camera_bundle = OrthographicCameraBundle::new_2d();
     camera_bundle.orthographic_projection.window_origin = WindowOrigin::Center;
@@ -126,6 +201,8 @@ fn init(
 
     asset_server.watch_for_changes().unwrap();
 
+    commands.insert_resource(ElementsFile(asset_server.load("elements.ron")));
+
     let dark = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.7).into());
     let transparent = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.0).into());
 
@@ -148,36 +225,16 @@ fn init(
             ..Default::default()
         })
         .with_children(|parent| {
-            for &(element, name, hotkey) in PALETTE {
-                parent
-                    .spawn_bundle(TextBundle {
-                        style: Style {
-                            size: Size {
-                                width: Val::Auto,
-                                height: Val::Px(20.0),
-                            },
-                            margin: Rect::all(Val::Px(10.0)),
-                            ..Default::default()
-                        },
-                        text: Text::with_section(
-                            format!("[{:?}] {}", hotkey, name),
-                            TextStyle {
-                                font: asset_server.load("menu.ttf"),
-                                font_size: 20.0,
-                                color: Color::WHITE,
-                            },
-                            TextAlignment {
-                                vertical: VerticalAlign::Center,
-                                horizontal: HorizontalAlign::Center,
-                            },
-                        ),
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        align_items: AlignItems::Center,
                         ..Default::default()
-                    })
-                    .insert(PaletteItem {
-                        paint: element,
-                        hotkey,
-                    });
-            }
+                    },
+                    material: transparent.clone(),
+                    ..Default::default()
+                })
+                .insert(PaletteBar);
 
             parent.spawn_bundle(NodeBundle {
                 style: Style {
@@ -192,6 +249,32 @@ fn init(
                 ..Default::default()
             });
 
+            parent
+                .spawn_bundle(TextBundle {
+                    style: Style {
+                        size: Size {
+                            width: Val::Auto,
+                            height: Val::Px(20.0),
+                        },
+                        margin: Rect::all(Val::Px(10.0)),
+                        ..Default::default()
+                    },
+                    text: Text::with_section(
+                        format!("Seed {}", simulation.seed()),
+                        TextStyle {
+                            font: asset_server.load("menu.ttf"),
+                            font_size: 20.0,
+                            color: Color::GRAY,
+                        },
+                        TextAlignment {
+                            vertical: VerticalAlign::Center,
+                            horizontal: HorizontalAlign::Center,
+                        },
+                    ),
+                    ..Default::default()
+                })
+                .insert(SeedLabel);
+
             parent
                 .spawn_bundle(NodeBundle {
                     style: Style {
@@ -333,19 +416,49 @@ fn init(
 
 fn update_visuals(
     brush: Res<Brush>,
-    mut tiles: Query<(&Element, &mut Material), Changed<Element>>,
+    overlay: Res<HeatOverlay>,
+    mut simulation: ResMut<Simulation>,
+    mut tilemap: ResMut<Tilemap>,
     mut palette: Query<(&PaletteItem, &mut Text)>,
+    mut seed: Query<&mut Text, (With<SeedLabel>, Without<PaletteItem>)>,
     mut slider: Query<&mut Style, With<BrushSlider>>,
 ) {
-    for (element, mut material) in tiles.iter_mut() {
-        material.0 = match element {
-            Element::Air => 0,
-            Element::Rock => 1,
-            Element::Water => 2,
-            Element::Sand(_) => 3,
+    let cells: Vec<_> = if overlay.is_changed() {
+        simulation.iter().collect()
+    } else {
+        simulation.changes().collect()
+    };
+
+    for (x, y) in cells {
+        if overlay.0 {
+            tilemap.set(x, y, Material::heat(simulation.temperature(x, y).unwrap()));
+            continue;
+        }
+
+        let element = simulation.get(x, y).unwrap();
+        let definition = simulation.elements().get(element);
+
+        let look = match (element, definition.state) {
+            _ if simulation.is_sparking(x, y) => Look::Spark,
+            (Element::Air, _) => Look::Empty,
+            (_, State::Solid) => Look::Solid,
+            (_, State::Liquid) => Look::Liquid,
+            (_, State::Powder) => Look::Powder,
+            (_, State::Gas) => Look::Gas,
         };
+
+        tilemap.set(
+            x,
+            y,
+            Material {
+                colour: definition.rgb(),
+                look,
+            },
+        );
     }
 
+    simulation.clear_changes();
+
     for (item, mut text) in palette.iter_mut() {
         let color = if item.paint == brush.paint {
             Color::WHITE
@@ -356,6 +469,14 @@ fn update_visuals(
         text.sections[0].style.color = color;
     }
 
+    let label = format!("Seed {}", simulation.seed());
+
+    for mut text in seed.iter_mut() {
+        if text.sections[0].value != label {
+            text.sections[0].value = label.clone();
+        }
+    }
+
     let precession = (brush.size - BRUSH_SIZE.start) as f32 / BRUSH_SIZE.len() as f32;
 
     for mut slider in slider.iter_mut() {
@@ -365,11 +486,18 @@ fn update_visuals(
 
 fn brush(
     mut brush: ResMut<Brush>,
+    keys: Res<Input<KeyCode>>,
     mut keyboard: EventReader<KeyboardInput>,
     mut mouse: EventReader<MouseWheel>,
     palette: Query<&PaletteItem>,
 ) {
+    let shortcut = keys.pressed(KeyCode::LControl) || keys.pressed(KeyCode::RControl);
+
     for event in keyboard.iter() {
+        if shortcut {
+            continue;
+        }
+
         if let &KeyboardInput {
             key_code: Some(key),
             state: ElementState::Pressed,
@@ -393,14 +521,148 @@ fn brush(
     }
 }
 
+fn scenes(keys: Res<Input<KeyCode>>, mut simulation: ResMut<Simulation>) {
+    if !keys.pressed(KeyCode::LControl) && !keys.pressed(KeyCode::RControl) {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::S) {
+        match scene::save(&simulation, SCENE_PATH) {
+            Ok(()) => info!("Saved scene to {}", SCENE_PATH),
+            Err(error) => error!("Could not save scene to {}: {}", SCENE_PATH, error),
+        }
+    } else if keys.just_pressed(KeyCode::O) {
+        let (width, height) = (simulation.width(), simulation.height());
+
+        match scene::load(SCENE_PATH, width, height, simulation.elements()) {
+            Ok(mut loaded) => {
+                loaded.set_borders(simulation.borders());
+                *simulation = loaded;
+                info!("Loaded scene from {}", SCENE_PATH);
+            }
+            Err(error) => error!("Could not load scene from {}: {}", SCENE_PATH, error),
+        }
+    } else if keys.just_pressed(KeyCode::E) {
+        match scene::export_png(&simulation, IMAGE_PATH) {
+            Ok(()) => info!("Exported scene to {}", IMAGE_PATH),
+            Err(error) => error!("Could not export scene to {}: {}", IMAGE_PATH, error),
+        }
+    } else if keys.just_pressed(KeyCode::I) {
+        let (width, height, seed) = (simulation.width(), simulation.height(), simulation.seed());
+
+        match scene::import_png(IMAGE_PATH, width, height, seed, simulation.elements()) {
+            Ok(mut imported) => {
+                imported.set_borders(simulation.borders());
+                *simulation = imported;
+                info!("Imported scene from {}", IMAGE_PATH);
+            }
+            Err(error) => error!("Could not import scene from {}: {}", IMAGE_PATH, error),
+        }
+    }
+}
+
+fn overlay(keys: Res<Input<KeyCode>>, mut overlay: ResMut<HeatOverlay>) {
+    let shortcut = keys.pressed(KeyCode::LControl) || keys.pressed(KeyCode::RControl);
+
+    if shortcut && keys.just_pressed(KeyCode::T) {
+        overlay.0 = !overlay.0;
+    }
+}
+
+impl AssetLoader for ElementsLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let table = ElementTable::parse(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(ElementsAsset(table)));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["elements.ron"]
+    }
+}
+
+fn reload_elements(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<ElementsAsset>>,
+    mut simulation: ResMut<Simulation>,
+    file: Res<ElementsFile>,
+    assets: Res<Assets<ElementsAsset>>,
+    asset_server: Res<AssetServer>,
+    bar: Query<Entity, With<PaletteBar>>,
+    items: Query<Entity, With<PaletteItem>>,
+) {
+    let reloaded = events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => *handle == file.0,
+        AssetEvent::Removed { .. } => false,
+    });
+
+    let table = match assets.get(&file.0) {
+        Some(ElementsAsset(table)) if reloaded => table,
+        _ => return,
+    };
+
+    simulation.set_elements(table.clone());
+
+    for item in items.iter() {
+        commands.entity(item).despawn_recursive();
+    }
+
+    for bar in bar.iter() {
+        commands.entity(bar).with_children(|parent| {
+            for (element, definition) in table.iter() {
+                let wanted = definition.hotkey.map(|key| key.to_ascii_uppercase());
+                let (label, hotkey) = match HOTKEYS.iter().find(|&&(key, _)| Some(key) == wanted) {
+                    Some(&(label, hotkey)) => (label, hotkey),
+                    None => continue,
+                };
+
+                parent
+                    .spawn_bundle(TextBundle {
+                        style: Style {
+                            size: Size {
+                                width: Val::Auto,
+                                height: Val::Px(20.0),
+                            },
+                            margin: Rect::all(Val::Px(10.0)),
+                            ..Default::default()
+                        },
+                        text: Text::with_section(
+                            format!("[{}] {}", label, definition.name),
+                            TextStyle {
+                                font: asset_server.load("menu.ttf"),
+                                font_size: 20.0,
+                                color: Color::WHITE,
+                            },
+                            TextAlignment {
+                                vertical: VerticalAlign::Center,
+                                horizontal: HorizontalAlign::Center,
+                            },
+                        ),
+                        ..Default::default()
+                    })
+                    .insert(PaletteItem {
+                        paint: element,
+                        hotkey,
+                    });
+            }
+        });
+    }
+}
+
 fn change_element(
     windows: Res<Windows>,
     brush: Res<Brush>,
     mouse: Res<Input<MouseButton>>,
     tilemap: Res<Tilemap>,
+    mut simulation: ResMut<Simulation>,
     mut tutorial: ResMut<TutorialTimer>,
     camera: Query<&Transform, With<ViewCamera>>,
-    mut tiles: Query<&mut Element>,
 ) {
     let target = {
         let mut pressed_iter = mouse.get_pressed();
@@ -435,19 +697,7 @@ fn change_element(
 
     for x_offset in offsets.clone() {
         for y_offset in offsets.clone() {
-            let x = x + x_offset;
-            let y = y + y_offset;
-
-            let tile = match tilemap.get(x, y) {
-                Some(tile) => tile,
-                None => continue,
-            };
-
-            let element = tiles.get_component_mut::<Element>(tile).ok();
-
-            if let Some(mut element) = element {
-                *element = target;
-            }
+            simulation.set(x + x_offset, y + y_offset, target);
         }
     }
 }
@@ -475,106 +725,68 @@ fn tutorial(
     }
 }
 
-fn rules(
-    queries: QuerySet<(
-        Query<&mut Element>,
-        Query<
-            (
-                Entity,
-                &TilePosition,
-                Option<&UpNeighbour<Element>>,
-                Option<&DownNeighbour<Element>>,
-                Option<&LeftNeighbour<Element>>,
-                Option<&RightNeighbour<Element>>,
-            ),
-            Or<(
-                Changed<Element>,
-                Changed<UpNeighbour<Element>>,
-                Changed<DownNeighbour<Element>>,
-                Changed<LeftNeighbour<Element>>,
-                Changed<RightNeighbour<Element>>,
-            )>,
-        >,
-    )>,
-    tilemap: Res<Tilemap>,
-) {
-    for (entity, &TilePosition(x, y), up, down, left, right) in queries.q1().iter() {
-        let mut element = unsafe { queries.q0().get_unchecked(entity) }.unwrap();
-
-        if let Element::Air = *element {
-            continue;
-        }
+fn simulate(mut simulation: ResMut<Simulation>) {
+    simulation.step();
+}
 
-        let up = up.map(|x| x.0);
-        let down = down.map(|x| x.0);
-        let left = left.map(|x| x.0);
-        let right = right.map(|x| x.0);
-
-        let (dest_x, dest_y, dest_element) = {
-            match *element {
-                Element::Air => continue,
-                Element::Rock => match (up, down, left, right) {
-                    (Some(Element::Rock) | None, _, _, _) => continue,
-                    (_, Some(Element::Rock) | None, _, _) => continue,
-                    (_, _, Some(Element::Rock) | None, _) => continue,
-                    (_, _, _, Some(Element::Rock) | None) => continue,
-                    _ => (x, y, Element::Sand(0)),
-                },
-                Element::Water => match down {
-                    Some(Element::Air) => (x, y - 1, Element::Water),
-                    _ => (x + destabilize_offset(left, right, 5.0), y, Element::Water),
-                },
-                Element::Sand(_) => match down {
-                    Some(Element::Air | Element::Water) => (x, y - 1, Element::Sand(0)),
-                    Some(Element::Rock) | None => (x, y, Element::Sand(0)),
-                    Some(Element::Sand(distance)) => {
-                        let strength =
-                            distance + support_strength(left) + support_strength(right) + 1;
-
-                        if strength < 3 {
-                            (x, y, Element::Sand(strength))
-                        } else {
-                            (
-                                x + destabilize_offset(left, right, 1.3),
-                                y,
-                                Element::Sand(0),
-                            )
-                        }
-                    }
-                },
-            }
-        };
+/// Reads the left, right, bottom and top borders from a list like `solid,void,wrap,solid`.
+fn parse_borders(list: &str) -> Option<Borders> {
+    let mut borders = list.split(',').map(|border| match border.trim() {
+        "solid" => Some(Border::Solid),
+        "void" => Some(Border::Void),
+        "wrap" => Some(Border::Wrap),
+        _ => None,
+    });
+
+    let parsed = Borders {
+        left: borders.next()??,
+        right: borders.next()??,
+        bottom: borders.next()??,
+        top: borders.next()??,
+    };
 
-        if dest_x == x && dest_y == y {
-            if *element != dest_element {
-                *element = dest_element;
-            }
-        } else if let Some(target) = tilemap.get(dest_x, dest_y) {
-            let mut target = unsafe { queries.q0().get_unchecked(target) }.unwrap();
-            std::mem::swap(&mut *target, &mut *element);
-        }
+    match borders.next() {
+        Some(_) => None,
+        None => Some(parsed),
     }
 }
 
-fn destabilize_offset(left: Option<Element>, right: Option<Element>, eagerness: f32) -> isize {
-    let min = if let Some(Element::Air | Element::Water) = left {
-        -eagerness
-    } else {
-        0.0
-    };
-    let max = if let Some(Element::Air | Element::Water) = right {
-        eagerness
-    } else {
-        0.0
-    };
+fn benchmark(seed: u64) {
+    const SIZE: usize = 1024;
+    const TICKS: u32 = 600;
 
-    (rand::thread_rng().gen_range(min..=max) as isize).signum()
-}
+    let mut simulation = Simulation::new(SIZE, SIZE, Element::Air, seed);
+    let size = SIZE as isize;
+
+    for x in 0..size {
+        for y in 0..size {
+            let element = match (x / 128, y / 128) {
+                (_, 0) if y < 16 => Element::Rock,
+                (column, row) if (column + row) % 3 == 0 => Element::Sand(0),
+                (column, row) if (column + row) % 3 == 1 => Element::Water,
+                _ => Element::Air,
+            };
 
-fn support_strength(element: Option<Element>) -> u8 {
-    match element {
-        Some(Element::Sand(_)) => 1,
-        Some(Element::Rock) => 2,
-        _ => 0,
+            simulation.set(x, y, element);
+        }
     }
+
+    let start = Instant::now();
+
+    for _ in 0..TICKS {
+        simulation.step();
+        simulation.clear_changes();
+    }
+
+    let elapsed = start.elapsed();
+
+    println!(
+        "{}x{} map, seed {}: {} ticks in {:.2?} ({:.1} ticks per second)",
+        SIZE,
+        SIZE,
+        seed,
+        TICKS,
+        elapsed,
+        TICKS as f64 / elapsed.as_secs_f64(),
+    );
 }
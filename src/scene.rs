@@ -0,0 +1,591 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    elements::{Element, ElementTable},
+    simulation::Simulation,
+};
+
+const MAGIC: &[u8; 4] = b"LDRT";
+const VERSION: u16 = 4;
+
+/// Scenes are stored as a small header followed by the names of the elements that were defined,
+/// and run-length encoded cells. Each name is a little-endian `u16` length and its UTF-8 bytes,
+/// so that custom elements, which are encoded by their position in that list, can be found again
+/// once the definitions change. Each run is a little-endian `u16` length, the three byte encoding
+/// of the repeated element and its temperature as a little-endian `f32`.
+pub fn save(simulation: &Simulation, path: impl AsRef<Path>) -> Result<(), SceneError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&(simulation.width() as u32).to_le_bytes())?;
+    writer.write_all(&(simulation.height() as u32).to_le_bytes())?;
+    writer.write_all(&simulation.seed().to_le_bytes())?;
+
+    let names = simulation
+        .elements()
+        .iter()
+        .map(|(_, definition)| &definition.name)
+        .collect::<Vec<_>>();
+
+    writer.write_all(&(names.len() as u16).to_le_bytes())?;
+
+    for name in names {
+        writer.write_all(&(name.len() as u16).to_le_bytes())?;
+        writer.write_all(name.as_bytes())?;
+    }
+
+    let mut run: Option<((Element, f32), u16)> = None;
+
+    for (x, y) in simulation.iter() {
+        let cell = (
+            simulation.get(x, y).unwrap(),
+            simulation.temperature(x, y).unwrap(),
+        );
+
+        run = match run {
+            Some((current, length)) if current == cell && length < u16::MAX => {
+                Some((current, length + 1))
+            }
+            Some((current, length)) => {
+                write_run(&mut writer, current, length)?;
+                Some((cell, 1))
+            }
+            None => Some((cell, 1)),
+        };
+    }
+
+    if let Some((current, length)) = run {
+        write_run(&mut writer, current, length)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Reads a scene saved by [`save`], refusing it unless it matches the given map dimensions and
+/// every element in it is defined in `elements`.
+pub fn load(
+    path: impl AsRef<Path>,
+    width: usize,
+    height: usize,
+    elements: &ElementTable,
+) -> Result<Simulation, SceneError> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+
+    if &magic != MAGIC {
+        return Err(SceneError::NotAScene);
+    }
+
+    let version = u16::from_le_bytes(read_array(&mut reader)?);
+
+    if version != VERSION {
+        return Err(SceneError::Version(version));
+    }
+
+    let found = (
+        u32::from_le_bytes(read_array(&mut reader)?) as usize,
+        u32::from_le_bytes(read_array(&mut reader)?) as usize,
+    );
+
+    if found != (width, height) {
+        return Err(SceneError::Size {
+            expected: (width, height),
+            found,
+        });
+    }
+
+    let seed = u64::from_le_bytes(read_array(&mut reader)?);
+
+    let count = u16::from_le_bytes(read_array(&mut reader)?);
+    let mut saved = Vec::with_capacity(count as usize);
+
+    for _ in 0..count {
+        let length = u16::from_le_bytes(read_array(&mut reader)?);
+        let mut name = vec![0; length as usize];
+        reader.read_exact(&mut name)?;
+
+        let name = String::from_utf8(name)
+            .map_err(|_| SceneError::Corrupt("element name is not UTF-8"))?;
+
+        saved.push(name);
+    }
+
+    let mut simulation = Simulation::new(width, height, Element::Air, seed);
+    simulation.set_elements(elements.clone());
+    let mut cells = simulation.iter().collect::<Vec<_>>().into_iter();
+
+    while cells.len() > 0 {
+        let length = u16::from_le_bytes(read_array(&mut reader)?);
+        let element = match decode(read_array(&mut reader)?)? {
+            Element::Custom(index) => {
+                let name = saved
+                    .get(index as usize)
+                    .ok_or(SceneError::Corrupt("unknown element"))?;
+
+                elements
+                    .find(name)
+                    .ok_or_else(|| SceneError::UnknownElement(name.clone()))?
+            }
+            element => element,
+        };
+        let temperature = f32::from_le_bytes(read_array(&mut reader)?);
+
+        if length as usize > cells.len() {
+            return Err(SceneError::Corrupt("run extends past the end of the map"));
+        }
+
+        for (x, y) in cells.by_ref().take(length as usize) {
+            simulation.set(x, y, element);
+            simulation.set_temperature(x, y, temperature);
+        }
+    }
+
+    Ok(simulation)
+}
+
+/// Writes the map as an indexed PNG whose palette holds the colour of every defined element.
+pub fn export_png(simulation: &Simulation, path: impl AsRef<Path>) -> Result<(), SceneError> {
+    let writer = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(
+        writer,
+        simulation.width() as u32,
+        simulation.height() as u32,
+    );
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(
+        simulation
+            .elements()
+            .iter()
+            .flat_map(|(_, definition)| definition.rgb())
+            .collect(),
+    );
+
+    let height = simulation.height() as isize;
+    let data = simulation
+        .iter()
+        .map(|(x, y)| {
+            let element = simulation.get(x, height - 1 - y).unwrap();
+            simulation.elements().index(element) as u8
+        })
+        .collect::<Vec<_>>();
+
+    encoder.write_header()?.write_image_data(&data)?;
+
+    Ok(())
+}
+
+/// Reads a PNG of exactly the map's dimensions, converting each pixel to the element of that
+/// colour in `elements`.
+///
+/// Any pixel whose colour is not in the palette fails the whole import, and the error lists
+/// where they are.
+pub fn import_png(
+    path: impl AsRef<Path>,
+    width: usize,
+    height: usize,
+    seed: u64,
+    elements: &ElementTable,
+) -> Result<Simulation, SceneError> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::EXPAND);
+
+    let (info, mut reader) = decoder.read_info()?;
+
+    let found = (info.width as usize, info.height as usize);
+
+    if found != (width, height) {
+        return Err(SceneError::Size {
+            expected: (width, height),
+            found,
+        });
+    }
+
+    let channels = match (info.color_type, info.bit_depth) {
+        (png::ColorType::RGB, png::BitDepth::Eight) => 3,
+        (png::ColorType::RGBA, png::BitDepth::Eight) => 4,
+        _ => {
+            return Err(SceneError::Corrupt(
+                "image must be 8-bit indexed, RGB or RGBA",
+            ))
+        }
+    };
+
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data)?;
+
+    let mut simulation = Simulation::new(width, height, Element::Air, seed);
+    simulation.set_elements(elements.clone());
+
+    let mut unknown = Vec::new();
+
+    for (x, y) in simulation.iter() {
+        let row = height - 1 - y as usize;
+        let start = row * info.line_size + x as usize * channels;
+        let colour = [data[start], data[start + 1], data[start + 2]];
+
+        match elements
+            .iter()
+            .find(|(_, definition)| definition.rgb() == colour)
+        {
+            Some((element, _)) => simulation.set(x, y, element),
+            None => unknown.push((x as usize, row, colour)),
+        }
+    }
+
+    if !unknown.is_empty() {
+        unknown.sort_by_key(|&(x, y, _)| (y, x));
+        return Err(SceneError::UnknownColours(unknown));
+    }
+
+    Ok(simulation)
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Decoding(png::DecodingError),
+    Encoding(png::EncodingError),
+    NotAScene,
+    Version(u16),
+    Size {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    Corrupt(&'static str),
+    /// A scene holds an element that is no longer defined.
+    UnknownElement(String),
+    /// Image pixels, in image coordinates, whose colour does not match any element.
+    UnknownColours(Vec<(usize, usize, [u8; 3])>),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(error) => write!(f, "{}", error),
+            SceneError::Decoding(error) => write!(f, "{}", error),
+            SceneError::Encoding(error) => write!(f, "{}", error),
+            SceneError::NotAScene => write!(f, "not a scene file"),
+            SceneError::Version(version) => write!(
+                f,
+                "scene was saved with format version {}, but only version {} is supported",
+                version, VERSION
+            ),
+            SceneError::Size { expected, found } => write!(
+                f,
+                "scene is {}x{}, but the map is {}x{}",
+                found.0, found.1, expected.0, expected.1
+            ),
+            SceneError::Corrupt(reason) => write!(f, "scene is corrupt: {}", reason),
+            SceneError::UnknownElement(name) => {
+                write!(f, "scene holds element {}, which is not defined", name)
+            }
+            SceneError::UnknownColours(pixels) => {
+                write!(f, "{} pixels have unknown colours:", pixels.len())?;
+
+                for (x, y, [r, g, b]) in pixels.iter().take(8) {
+                    write!(f, " #{:02x}{:02x}{:02x} at ({}, {})", r, g, b, x, y)?;
+                }
+
+                if pixels.len() > 8 {
+                    write!(f, " ...")?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for SceneError {}
+
+impl From<io::Error> for SceneError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => SceneError::Corrupt("file ends too early"),
+            _ => SceneError::Io(error),
+        }
+    }
+}
+
+impl From<png::DecodingError> for SceneError {
+    fn from(error: png::DecodingError) -> Self {
+        match error {
+            png::DecodingError::IoError(error) => error.into(),
+            error => SceneError::Decoding(error),
+        }
+    }
+}
+
+impl From<png::EncodingError> for SceneError {
+    fn from(error: png::EncodingError) -> Self {
+        match error {
+            png::EncodingError::IoError(error) => error.into(),
+            error => SceneError::Encoding(error),
+        }
+    }
+}
+
+fn write_run(
+    writer: &mut impl Write,
+    (element, temperature): (Element, f32),
+    length: u16,
+) -> io::Result<()> {
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(&encode(element))?;
+    writer.write_all(&temperature.to_le_bytes())
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buffer = [0; N];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn encode(element: Element) -> [u8; 3] {
+    match element {
+        Element::Air => [0, 0, 0],
+        Element::Rock => [1, 0, 0],
+        Element::Water => [2, 0, 0],
+        Element::Sand(distance) => [3, distance, 0],
+        Element::Custom(index) => [4, index, 0],
+        Element::Fire(age) => [5, age, 0],
+        Element::Smoke(age) => [6, age, 0],
+        Element::Steam(age) => [7, age, 0],
+        Element::Lava => [8, 0, 0],
+        Element::Obsidian => [9, 0, 0],
+        Element::Acid(used) => [10, used, 0],
+        Element::WetSand(distance, age) => [11, distance, age],
+        Element::Seed => [12, 0, 0],
+        Element::Plant(distance) => [13, distance, 0],
+        Element::Wall => [14, 0, 0],
+        Element::Spark => [15, 0, 0],
+    }
+}
+
+fn decode(bytes: [u8; 3]) -> Result<Element, SceneError> {
+    match bytes {
+        [0, _, _] => Ok(Element::Air),
+        [1, _, _] => Ok(Element::Rock),
+        [2, _, _] => Ok(Element::Water),
+        [3, distance, _] => Ok(Element::Sand(distance)),
+        [4, index, _] => Ok(Element::Custom(index)),
+        [5, age, _] => Ok(Element::Fire(age)),
+        [6, age, _] => Ok(Element::Smoke(age)),
+        [7, age, _] => Ok(Element::Steam(age)),
+        [8, _, _] => Ok(Element::Lava),
+        [9, _, _] => Ok(Element::Obsidian),
+        [10, used, _] => Ok(Element::Acid(used)),
+        [11, distance, age] => Ok(Element::WetSand(distance, age)),
+        [12, _, _] => Ok(Element::Seed),
+        [13, distance, _] => Ok(Element::Plant(distance)),
+        [14, _, _] => Ok(Element::Wall),
+        [15, _, _] => Ok(Element::Spark),
+        _ => Err(SceneError::Corrupt("unknown element")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_round_trip() {
+        let path = std::env::temp_dir().join("loose-dirt-round-trip.ldirt");
+        let elements = [
+            Element::Sand(2),
+            Element::Fire(3),
+            Element::Acid(4),
+            Element::WetSand(5, 25),
+            Element::Plant(6),
+            Element::Custom(7),
+        ];
+
+        let mut simulation = Simulation::new(8, 4, Element::Wall, 42);
+
+        for (x, &element) in elements.iter().enumerate() {
+            simulation.set(x as isize, 1, element);
+        }
+
+        simulation.set(0, 2, Element::Lava);
+
+        for _ in 0..50 {
+            simulation.step();
+        }
+
+        save(&simulation, &path).unwrap();
+        let loaded = load(&path, 8, 4, simulation.elements()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.seed(), 42);
+
+        for (x, y) in simulation.iter() {
+            assert_eq!(loaded.get(x, y), simulation.get(x, y));
+            assert_eq!(loaded.temperature(x, y), simulation.temperature(x, y));
+        }
+    }
+
+    #[test]
+    fn custom_elements_are_found_by_name() {
+        let path = std::env::temp_dir().join("loose-dirt-custom-elements.ldirt");
+        let source = include_str!("../assets/elements.ron").replacen(
+            "\n[\n",
+            "\n[\n(name: \"Dust\", colour: (0.3, 0.3, 0.3), density: 1.2, state: Powder),\n",
+            1,
+        );
+        let elements = ElementTable::parse(source.as_bytes()).unwrap();
+
+        let mut simulation = Simulation::new(4, 4, Element::Air, 0);
+        let oil = simulation.elements().find("Oil").unwrap();
+        simulation.set(1, 0, oil);
+
+        save(&simulation, &path).unwrap();
+        let loaded = load(&path, 4, 4, &elements).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_ne!(elements.find("Oil"), Some(oil));
+        assert_eq!(loaded.get(1, 0), elements.find("Oil"));
+    }
+
+    #[test]
+    fn supports_and_seed_round_trip() {
+        let path = std::env::temp_dir().join("loose-dirt-supports.ldirt");
+        let mut simulation = Simulation::new(6, 3, Element::Air, 1234);
+
+        for x in 0..6 {
+            simulation.set(x, 0, Element::Sand(x as u8));
+        }
+
+        simulation.set(2, 1, Element::Water);
+
+        save(&simulation, &path).unwrap();
+        let loaded = load(&path, 6, 3, simulation.elements()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.seed(), 1234);
+        assert_eq!((loaded.width(), loaded.height()), (6, 3));
+        assert!(simulation
+            .iter()
+            .all(|(x, y)| loaded.get(x, y) == simulation.get(x, y)));
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let path = std::env::temp_dir().join("loose-dirt-version.ldirt");
+        let simulation = Simulation::new(4, 4, Element::Sand(0), 0);
+
+        save(&simulation, &path).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+
+        let loaded = load(&path, 4, 4, simulation.elements());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(loaded, Err(SceneError::Version(version)) if version == VERSION + 1));
+    }
+
+    #[test]
+    fn other_sizes_are_rejected() {
+        let path = std::env::temp_dir().join("loose-dirt-size.ldirt");
+        let simulation = Simulation::new(8, 4, Element::Sand(0), 0);
+
+        save(&simulation, &path).unwrap();
+        let loaded = load(&path, 4, 8, simulation.elements());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            loaded,
+            Err(SceneError::Size {
+                expected: (4, 8),
+                found: (8, 4),
+            })
+        ));
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let path = std::env::temp_dir().join("loose-dirt-truncated.ldirt");
+        let mut simulation = Simulation::new(4, 4, Element::Air, 0);
+        simulation.set(1, 1, Element::Water);
+
+        save(&simulation, &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        // Cutting the file off in the middle of the cells, of the element names and of the
+        // header.
+        for length in [bytes.len() - 3, 30, 10] {
+            std::fs::write(&path, &bytes[..length]).unwrap();
+
+            assert!(matches!(
+                load(&path, 4, 4, simulation.elements()),
+                Err(SceneError::Corrupt(_))
+            ));
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn images_round_trip() {
+        let path = std::env::temp_dir().join("loose-dirt-round-trip.png");
+        let mut simulation = Simulation::new(6, 4, Element::Air, 0);
+        let oil = simulation.elements().find("Oil").unwrap();
+
+        simulation.set(0, 0, Element::Rock);
+        simulation.set(1, 0, Element::Sand(0));
+        simulation.set(2, 0, Element::Water);
+        simulation.set(2, 1, oil);
+        simulation.set(5, 3, Element::Wall);
+
+        export_png(&simulation, &path).unwrap();
+        let imported = import_png(&path, 6, 4, 0, simulation.elements()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        for (x, y) in simulation.iter() {
+            assert_eq!(imported.get(x, y), simulation.get(x, y));
+        }
+    }
+
+    #[test]
+    fn unknown_colours_are_reported_with_their_coordinates() {
+        let path = std::env::temp_dir().join("loose-dirt-unknown-colours.png");
+        let elements = ElementTable::default();
+        let mut data = elements.get(Element::Rock).rgb().repeat(3 * 2);
+
+        // Two pixels in image coordinates, which count rows from the top.
+        data[2 * 3..3 * 3].copy_from_slice(&[1, 2, 3]);
+        data[3 * 3..4 * 3].copy_from_slice(&[4, 5, 6]);
+
+        let mut encoder = png::Encoder::new(File::create(&path).unwrap(), 3, 2);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&data)
+            .unwrap();
+
+        let imported = import_png(&path, 3, 2, 0, &elements);
+        std::fs::remove_file(&path).unwrap();
+
+        match imported {
+            Err(SceneError::UnknownColours(pixels)) => {
+                assert_eq!(pixels, [(2, 0, [1, 2, 3]), (0, 1, [4, 5, 6])])
+            }
+            _ => panic!("unknown colours were not reported"),
+        }
+    }
+}
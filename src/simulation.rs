@@ -0,0 +1,1974 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    bitset::BitSet,
+    elements::{Element, ElementTable, Emitter, State},
+};
+
+/// Offsets of the eight cells surrounding any given cell.
+pub const NEIGHBOURHOOD: [(isize, isize); 8] = [
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+    (-1, 0),
+    (1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+/// Offsets of the four cells sharing an edge with any given cell.
+const ADJACENT: [(isize, isize); 4] = [(0, 1), (-1, 0), (1, 0), (0, -1)];
+
+/// Temperature, in °C, of cells whose element does not define one.
+pub const AMBIENT_TEMPERATURE: f32 = 20.0;
+
+/// Average number of ticks a liquid surface that cannot spread waits before searching for a
+/// lower place to spill into, as searching is costly.
+const SPILL_INTERVAL: u32 = 16;
+
+/// Acceleration of falling powders and liquids, in cells per tick per tick.
+const GRAVITY: f32 = 0.2;
+
+/// Fastest anything moves, in cells per tick.
+const MAX_SPEED: f32 = 8.0;
+
+/// Powders and liquids landing faster than this, in cells per tick, are thrown sideways.
+const SPLASH_SPEED: f32 = 2.0;
+
+/// Fraction of their sideways speed that powders and liquids keep per tick while resting on
+/// something.
+const FRICTION: f32 = 0.8;
+
+/// Speed, in cells per tick, at which powders and liquids right next to an explosion are
+/// thrown away from it.
+const BLAST_SPEED: f32 = MAX_SPEED;
+
+/// How many cells of plant water is drawn up through. Parts of a plant further than this from
+/// water stop growing and wither.
+const PLANT_REACH: u8 = 24;
+
+/// Chance per tick that a plant touching water drinks one cell of it.
+const DRINK_CHANCE: f32 = 0.002;
+
+/// Chance per tick that a watered plant grows a new cell.
+const GROWTH_CHANCE: f32 = 0.05;
+
+/// Chance per tick that a plant cut off from water withers away.
+const WITHER_CHANCE: f32 = 0.02;
+
+/// How many cells in front of it a fan blows.
+const FAN_REACH: isize = 8;
+
+/// How many cells a falling body of rock has to drop before it shatters into sand on landing.
+const SHATTER_DROP: u8 = 8;
+
+/// Heat flows smaller than this, in °C, are ignored so that cells can settle and fall asleep.
+const HEAT_FLOW_THRESHOLD: f32 = 0.01;
+
+/// How many ticks a conductive cell carries a spark for, passing it on to its neighbours.
+const SPARK_TICKS: u8 = 2;
+
+/// How many ticks a conductive cell needs after carrying a spark before it can carry another,
+/// so that sparks keep travelling away from where they came from.
+const RECOVERY_TICKS: u8 = 4;
+
+/// What happens at an edge of the map.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Border {
+    /// Holds everything in like a wall, and anchors rock.
+    #[default]
+    Solid,
+    /// Lets anything that is not solid fall out of the map.
+    Void,
+    /// Leads around to the opposite edge.
+    Wrap,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Borders {
+    pub left: Border,
+    pub right: Border,
+    pub bottom: Border,
+    pub top: Border,
+}
+
+pub struct Simulation {
+    width: isize,
+    height: isize,
+    borders: Borders,
+    cells: Box<[Element]>,
+    temperatures: Box<[f32]>,
+    /// Horizontal and vertical speed of each cell, in cells per tick.
+    velocities: Box<[(f32, f32)]>,
+    /// Ticks left until each cell can carry a spark again. Cells carry a spark while this is
+    /// above `RECOVERY_TICKS`.
+    charges: Box<[u8]>,
+    elements: ElementTable,
+    awake: BitSet,
+    active: BitSet,
+    warm: BitSet,
+    warming: BitSet,
+    charged: BitSet,
+    charging: BitSet,
+    updated: BitSet,
+    changed: BitSet,
+    /// Rock cells that were placed or removed since rock was last checked for support.
+    unsettled: Vec<usize>,
+    /// Clusters of rock that lost their support, and the cells that belong to one of them.
+    bodies: Vec<Body>,
+    falling: BitSet,
+    /// Scratch space for searching through liquid bodies and rock clusters, kept empty between
+    /// searches.
+    visited: BitSet,
+    anchored: BitSet,
+    frontier: BinaryHeap<Reverse<usize>>,
+    explored: Vec<usize>,
+    /// Liquid cells at or below a surface that found nowhere lower to spill into this tick.
+    stagnant: BitSet,
+    seed: u64,
+    rng: StdRng,
+}
+
+impl Simulation {
+    /// Creates a simulation whose stochastic rules are driven by `seed`, so that the same
+    /// seed and the same sequence of edits always produce the same grid.
+    pub fn new(width: usize, height: usize, template: Element, seed: u64) -> Self {
+        Self {
+            width: width as isize,
+            height: height as isize,
+            borders: Borders::default(),
+            cells: vec![template; width * height].into_boxed_slice(),
+            temperatures: vec![AMBIENT_TEMPERATURE; width * height].into_boxed_slice(),
+            velocities: vec![(0.0, 0.0); width * height].into_boxed_slice(),
+            charges: vec![0; width * height].into_boxed_slice(),
+            elements: ElementTable::default(),
+            awake: BitSet::full(width * height),
+            active: BitSet::new(width * height),
+            warm: BitSet::full(width * height),
+            warming: BitSet::new(width * height),
+            charged: BitSet::new(width * height),
+            charging: BitSet::new(width * height),
+            updated: BitSet::new(width * height),
+            changed: BitSet::full(width * height),
+            unsettled: Vec::new(),
+            bodies: Vec::new(),
+            falling: BitSet::new(width * height),
+            visited: BitSet::new(width * height),
+            anchored: BitSet::new(width * height),
+            frontier: BinaryHeap::new(),
+            explored: Vec::new(),
+            stagnant: BitSet::new(width * height),
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn elements(&self) -> &ElementTable {
+        &self.elements
+    }
+
+    /// Replaces the element definitions, re-evaluating and redrawing every cell.
+    ///
+    /// Custom elements are identified by their position in the table, so cells holding one are
+    /// matched up by name with the new definitions. Those that are no longer defined turn into
+    /// air.
+    pub fn set_elements(&mut self, elements: ElementTable) {
+        let len = self.cells.len();
+        let renamed = self
+            .elements
+            .iter()
+            .map(|(element, definition)| match element {
+                Element::Custom(_) => elements.find(&definition.name).unwrap_or(Element::Air),
+                builtin => builtin,
+            })
+            .collect::<Vec<_>>();
+
+        for cell in self.cells.iter_mut() {
+            if let Element::Custom(index) = *cell {
+                *cell = renamed.get(index as usize).copied().unwrap_or(Element::Air);
+            }
+        }
+
+        self.elements = elements;
+        self.awake = BitSet::full(len);
+        self.warm = BitSet::full(len);
+        self.changed = BitSet::full(len);
+    }
+
+    pub fn borders(&self) -> Borders {
+        self.borders
+    }
+
+    /// Changes what happens at the edges of the map, re-evaluating every cell.
+    pub fn set_borders(&mut self, borders: Borders) {
+        self.borders = borders;
+        self.awake = BitSet::full(self.cells.len());
+        self.unsettled.extend(0..self.cells.len());
+    }
+
+    pub fn width(&self) -> usize {
+        self.width as usize
+    }
+
+    pub fn height(&self) -> usize {
+        self.height as usize
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (isize, isize)> {
+        let width = self.width;
+        let height = self.height;
+
+        (0..height).flat_map(move |y| (0..width).map(move |x| (x, y)))
+    }
+
+    /// The element at the given position, wrapping around edges that wrap, or `None` past any
+    /// other edge.
+    pub fn get(&self, x: isize, y: isize) -> Option<Element> {
+        self.locate(x, y).map(|index| self.cells[index])
+    }
+
+    pub fn temperature(&self, x: isize, y: isize) -> Option<f32> {
+        self.locate(x, y).map(|index| self.temperatures[index])
+    }
+
+    /// Whether the cell at the given position is carrying a spark.
+    pub fn is_sparking(&self, x: isize, y: isize) -> bool {
+        matches!(self.locate(x, y), Some(index) if self.charges[index] > RECOVERY_TICKS)
+    }
+
+    /// Changes the temperature of the cell at the given position, without turning it into
+    /// another element even if it is past one of its thresholds.
+    pub fn set_temperature(&mut self, x: isize, y: isize, temperature: f32) {
+        if self.locate(x, y).is_some() {
+            self.heat(x, y, temperature);
+        }
+    }
+
+    /// Places `element` at its initial temperature, standing still and uncharged.
+    pub fn set(&mut self, x: isize, y: isize, element: Element) {
+        if let Some(index) = self.locate(x, y) {
+            self.velocities[index] = (0.0, 0.0);
+            self.charges[index] = 0;
+            self.heat(x, y, self.initial_temperature(element));
+            self.write(x, y, element);
+        }
+    }
+
+    /// Positions whose element or temperature changed since the last call to `clear_changes`.
+    pub fn changes(&self) -> impl Iterator<Item = (isize, isize)> + '_ {
+        let width = self.width;
+
+        self.changed
+            .iter()
+            .map(move |index| (index as isize % width, index as isize / width))
+    }
+
+    pub fn clear_changes(&mut self) {
+        self.changed.clear();
+    }
+
+    /// Advances the simulation by a single tick.
+    ///
+    /// Heat is conducted first, only around cells whose temperature changed in the previous
+    /// tick, and sparks move on. Rock that lost its support falls next. Then only cells next to
+    /// something that changed are evaluated. They are visited bottom to top so that falling
+    /// cells move at most once per tick.
+    pub fn step(&mut self) {
+        std::mem::swap(&mut self.warm, &mut self.warming);
+        self.warm.clear();
+
+        let warming = std::mem::take(&mut self.warming);
+
+        for index in warming.iter() {
+            self.conduct(index as isize % self.width, index as isize / self.width);
+        }
+
+        self.warming = warming;
+
+        std::mem::swap(&mut self.charged, &mut self.charging);
+        self.charged.clear();
+
+        let charging = std::mem::take(&mut self.charging);
+
+        for index in charging.iter() {
+            self.discharge(index as isize % self.width, index as isize / self.width);
+        }
+
+        self.charging = charging;
+
+        self.settle();
+        self.fall();
+
+        std::mem::swap(&mut self.awake, &mut self.active);
+        self.awake.clear();
+        self.updated.clear();
+        self.stagnant.clear();
+
+        let active = std::mem::take(&mut self.active);
+
+        for index in active.iter() {
+            if !self.updated.contains(index) {
+                let index = index as isize;
+                self.rules(index % self.width, index / self.width);
+            }
+        }
+
+        self.active = active;
+    }
+
+    fn rules(&mut self, x: isize, y: isize) {
+        let element = match self.get(x, y) {
+            Some(Element::Air) | None => return,
+            Some(element) => element,
+        };
+
+        let neighbours = self.neighbourhood(x, y);
+        let [_, up, _, left, right, _, down, _] = neighbours;
+
+        if self.react(x, y, element, neighbours) {
+            return;
+        }
+
+        let definition = self.elements.get(element);
+        let (state, wind, drains, powers) = (
+            definition.state,
+            definition.wind,
+            definition.drains,
+            definition.powers,
+        );
+
+        if state != State::Solid && self.falls_out(x, y) {
+            self.write(x, y, Element::Air);
+            return;
+        }
+
+        let element = match element {
+            Element::WetSand(distance, age)
+                if [up, left, right, down].contains(&Some(Element::Air)) =>
+            {
+                self.wake(x, y);
+
+                match self.grow_older(element, age) {
+                    Some(age) => {
+                        // Drying makes no difference to the neighbours, so it is stored without
+                        // waking them.
+                        let index = self.index(x, y);
+                        self.cells[index] = Element::WetSand(distance, age);
+                        self.cells[index]
+                    }
+                    None => Element::Sand(distance),
+                }
+            }
+            element => element,
+        };
+
+        if matches!(state, State::Powder | State::Liquid) {
+            if let Some((dest_x, dest_y)) = self.fly(x, y, element) {
+                self.swap(x, y, dest_x, dest_y, with_support(element, 0));
+                self.updated.insert(self.index(dest_x, dest_y));
+                return;
+            }
+        }
+
+        let (dest_x, dest_y, dest_element) = match (element, state) {
+            (Element::Plant(_), _) => {
+                let adjacent = [up, left, right, down];
+
+                match self.draw_water(x, y, adjacent) {
+                    Some(distance) => {
+                        self.grow(x, y, distance);
+                        (x, y, Element::Plant(distance))
+                    }
+                    None if self.rng.gen::<f32>() < WITHER_CHANCE => (x, y, Element::Air),
+                    None => {
+                        self.wake(x, y);
+                        return;
+                    }
+                }
+            }
+            (Element::Air, _) => return,
+            (_, State::Solid) => {
+                if wind != (0.0, 0.0) {
+                    self.blow(x, y, wind);
+                }
+
+                if drains {
+                    self.drain(x, y, [up, left, right, down]);
+                }
+
+                if powers {
+                    self.spark(x, y);
+                    self.wake(x, y);
+                }
+
+                if let Some(emitter) = self.elements.emitter(element) {
+                    self.emit(x, y, emitter);
+                }
+
+                return;
+            }
+            (Element::Spark, _) => {
+                self.spark(x, y);
+                self.ignite(x, y);
+                (x, y, Element::Air)
+            }
+            (Element::Seed, _)
+                if matches!(down, Some(Element::Sand(_) | Element::WetSand(..)))
+                    && [up, left, right, down].iter().any(|&cell| is_moist(cell)) =>
+            {
+                (x, y, Element::Plant(0))
+            }
+            (_, State::Powder) => {
+                let below = down.map(|down| (down, self.elements.get(down).state));
+
+                match below {
+                    _ if self.sinks_into(element, down) => (x, y - 1, element),
+                    Some((below, State::Powder)) => {
+                        let distance = match below {
+                            Element::Sand(distance) | Element::WetSand(distance, _) => distance,
+                            _ => 0,
+                        };
+                        let strength = distance
+                            .saturating_add(self.support_strength(left))
+                            .saturating_add(self.support_strength(right))
+                            .saturating_add(1);
+                        let cohesion = self.elements.repose(element).cohesion;
+
+                        if strength < cohesion {
+                            (x, y, with_support(element, strength))
+                        } else {
+                            // Any support past the cohesion makes no difference, so it is capped
+                            // to keep deep piles from counting up without limit.
+                            match self.slide(x, y, element) {
+                                Some((dest_x, dest_y)) => (dest_x, dest_y, element),
+                                None => (x, y, with_support(element, strength.min(cohesion))),
+                            }
+                        }
+                    }
+                    _ => (x, y, with_support(element, 0)),
+                }
+            }
+            (Element::Lava, _) => {
+                self.ignite(x, y);
+
+                let (dest_x, dest_y) = self.flow(x, y, element);
+                (dest_x, dest_y, element)
+            }
+            (Element::Acid(used), _) => {
+                let acid = self.corrode(x, y, element, used);
+                let (dest_x, dest_y) = self.flow(x, y, element);
+                (dest_x, dest_y, acid)
+            }
+            (_, State::Liquid) => {
+                let (dest_x, dest_y) = self.flow(x, y, element);
+                (dest_x, dest_y, element)
+            }
+            (Element::Fire(age), _) => {
+                self.ignite(x, y);
+
+                self.heat(x, y, self.initial_temperature(element));
+
+                let next = match self.grow_older(element, age) {
+                    Some(age) => Element::Fire(age),
+                    None => Element::Smoke(0),
+                };
+
+                let (dest_x, dest_y) = self.float(x, y, element);
+                (dest_x, dest_y, next)
+            }
+            (Element::Smoke(age), _) => match self.grow_older(element, age) {
+                Some(age) => {
+                    let (dest_x, dest_y) = self.float(x, y, element);
+                    (dest_x, dest_y, Element::Smoke(age))
+                }
+                None => (x, y, Element::Air),
+            },
+            (Element::Steam(age), _) => match self.grow_older(element, age) {
+                Some(age) => {
+                    let (dest_x, dest_y) = self.float(x, y, element);
+                    (dest_x, dest_y, Element::Steam(age))
+                }
+                None => (x, y, Element::Water),
+            },
+            (_, State::Gas) => {
+                let (dest_x, dest_y) = self.float(x, y, element);
+                (dest_x, dest_y, element)
+            }
+        };
+
+        if (dest_x, dest_y) != (x, y) {
+            if let Some(target) = self.get(dest_x, dest_y) {
+                if target != element {
+                    self.swap(x, y, dest_x, dest_y, dest_element);
+                    self.updated.insert(self.index(dest_x, dest_y));
+                    return;
+                }
+            }
+        }
+
+        self.write(x, y, dest_element);
+    }
+
+    /// Moves `element` from the first position to the second one, and whatever was there to the
+    /// first, along with their temperatures.
+    fn swap(&mut self, x: isize, y: isize, dest_x: isize, dest_y: isize, element: Element) {
+        let (from, to) = (self.index(x, y), self.index(dest_x, dest_y));
+        let target = self.cells[to];
+
+        self.store(to, dest_x, dest_y, element);
+        self.store(from, x, y, target);
+
+        if self.temperatures[from] != self.temperatures[to] {
+            self.temperatures.swap(from, to);
+            self.warm.insert(from);
+            self.warm.insert(to);
+        }
+
+        self.velocities.swap(from, to);
+
+        if self.charges[from] != self.charges[to] {
+            self.charges.swap(from, to);
+            self.charged.insert(from);
+            self.charged.insert(to);
+        }
+    }
+
+    /// Where a powder or liquid moving under its own momentum gets to this tick, if anywhere.
+    ///
+    /// Falling speeds it up, and landing fast throws it sideways. Sideways motion slows down
+    /// while it rests on something, and motion into anything it cannot pass through stops.
+    fn fly(&mut self, x: isize, y: isize, element: Element) -> Option<(isize, isize)> {
+        let index = self.index(x, y);
+        let (mut vx, mut vy) = self.velocities[index];
+
+        if self.sinks_into(element, self.get(x, y - 1)) {
+            vy = (vy - GRAVITY).max(-MAX_SPEED);
+        } else if (vx, vy) == (0.0, 0.0) {
+            return None;
+        } else {
+            if vy < -SPLASH_SPEED {
+                let direction = match vx {
+                    vx if vx != 0.0 => vx.signum(),
+                    _ if self.rng.gen() => 1.0,
+                    _ => -1.0,
+                };
+
+                vx += direction * -vy / 2.0;
+            }
+
+            vy = vy.max(0.0);
+            vx *= FRICTION;
+
+            if vx.abs() < 0.1 {
+                vx = 0.0;
+            }
+        }
+
+        vx = vx.clamp(-MAX_SPEED, MAX_SPEED);
+
+        let mut steps = round(vx.abs().max(vy.abs()));
+        let (mut dest_x, mut dest_y) = (x, y);
+
+        if vx == 0.0 {
+            // Falling straight down is by far the most common motion, so it is stepped through
+            // a cell at a time rather than along a slope.
+            let direction = vy.signum() as isize;
+
+            for _ in 0..steps {
+                if !self.flows_into(element, self.get(x, dest_y + direction)) {
+                    vy = 0.0;
+                    break;
+                }
+
+                dest_y += direction;
+            }
+
+            steps = 0;
+        }
+
+        for step in 1..=steps {
+            let fraction = step as f32 / steps as f32;
+            let next_x = x + round(vx * fraction);
+            let next_y = y + round(vy * fraction);
+
+            if self.flows_into(element, self.get(next_x, next_y)) {
+                dest_x = next_x;
+                dest_y = next_y;
+                continue;
+            }
+
+            let blocked_x = !self.flows_into(element, self.get(next_x, dest_y));
+            let blocked_y = !self.flows_into(element, self.get(dest_x, next_y));
+
+            if next_y != dest_y && blocked_y {
+                vy = 0.0;
+            }
+
+            if next_x != dest_x && (blocked_x || !blocked_y) {
+                vx = 0.0;
+            }
+
+            break;
+        }
+
+        self.velocities[index] = (vx, vy);
+
+        if (vx, vy) != (0.0, 0.0) {
+            self.wake(x, y);
+        }
+
+        if (dest_x, dest_y) != (x, y) {
+            Some((dest_x, dest_y))
+        } else {
+            None
+        }
+    }
+
+    /// How many cells of plant away from water the plant at the given position is, drinking
+    /// the water if it touches any, or `None` if it is too far.
+    fn draw_water(&mut self, x: isize, y: isize, adjacent: [Option<Element>; 4]) -> Option<u8> {
+        let mut moist = false;
+
+        for (&(dx, dy), &neighbour) in ADJACENT.iter().zip(&adjacent) {
+            if !is_moist(neighbour) {
+                continue;
+            }
+
+            moist = true;
+            self.wake(x, y);
+
+            if self.rng.gen::<f32>() < DRINK_CHANCE {
+                let dried = match neighbour {
+                    Some(Element::WetSand(distance, _)) => Element::Sand(distance),
+                    _ => Element::Air,
+                };
+
+                self.write(x + dx, y + dy, dried);
+                break;
+            }
+        }
+
+        if moist {
+            return Some(0);
+        }
+
+        // Plants grow diagonally, so water is drawn up from diagonal neighbours as well.
+        NEIGHBOURHOOD
+            .iter()
+            .filter_map(|&(dx, dy)| match self.get(x + dx, y + dy) {
+                Some(Element::Plant(distance)) => Some(distance.saturating_add(1)),
+                _ => None,
+            })
+            .min()
+            .filter(|&distance| distance <= PLANT_REACH)
+    }
+
+    /// Grows the plant at the given position into the air above it or diagonally above it,
+    /// as long as the new cell would touch no other part of the plant, so that it grows into
+    /// branching stems rather than a blob.
+    fn grow(&mut self, x: isize, y: isize, distance: u8) {
+        if distance >= PLANT_REACH {
+            return;
+        }
+
+        let dx = match self.rng.gen_range(0..4) {
+            0 => -1,
+            1 => 1,
+            _ => 0,
+        };
+        let (target_x, target_y) = (x + dx, y + 1);
+
+        if self.get(target_x, target_y) != Some(Element::Air) {
+            return;
+        }
+
+        self.wake(x, y);
+
+        let crowded = NEIGHBOURHOOD
+            .iter()
+            .map(|&(nx, ny)| (target_x + nx, target_y + ny))
+            .filter(|&position| position != (x, y))
+            .any(|(nx, ny)| matches!(self.get(nx, ny), Some(Element::Plant(_))));
+
+        if !crowded && self.rng.gen::<f32>() < GROWTH_CHANCE {
+            self.write(target_x, target_y, Element::Plant(distance + 1));
+            self.updated.insert(self.index(target_x, target_y));
+        }
+    }
+
+    /// Places the emitter's element in an empty cell next to the given position with the
+    /// emitter's chance, preferring the one below.
+    fn emit(&mut self, x: isize, y: isize, emitter: Emitter) {
+        self.wake(x, y);
+
+        if self.rng.gen::<f32>() >= emitter.chance {
+            return;
+        }
+
+        if let Some(&(dx, dy)) = ADJACENT
+            .iter()
+            .rev()
+            .find(|&&(dx, dy)| self.get(x + dx, y + dy) == Some(Element::Air))
+        {
+            self.set(x + dx, y + dy, emitter.element);
+            self.updated.insert(self.index(x + dx, y + dy));
+        }
+    }
+
+    /// Removes every powder, liquid and gas in the `adjacent` cells.
+    fn drain(&mut self, x: isize, y: isize, adjacent: [Option<Element>; 4]) {
+        for (&(dx, dy), &neighbour) in ADJACENT.iter().zip(&adjacent) {
+            match neighbour {
+                Some(Element::Air) | None => {}
+                Some(neighbour) if self.elements.get(neighbour).state != State::Solid => {
+                    self.write(x + dx, y + dy, Element::Air);
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Gives the powders and liquids in front of a fan its wind's velocity, up to the first
+    /// solid in the way.
+    fn blow(&mut self, x: isize, y: isize, wind: (f32, f32)) {
+        let sign = |speed: f32| match speed {
+            speed if speed > 0.0 => 1,
+            speed if speed < 0.0 => -1,
+            _ => 0,
+        };
+        let (dx, dy) = (sign(wind.0), sign(wind.1));
+
+        for distance in 1..=FAN_REACH {
+            let (target_x, target_y) = (x + dx * distance, y + dy * distance);
+
+            let index = match self.locate(target_x, target_y) {
+                Some(index) => index,
+                None => break,
+            };
+
+            match self.elements.get(self.cells[index]).state {
+                State::Solid => break,
+                State::Powder | State::Liquid => {
+                    self.velocities[index] = wind;
+                    self.awake.insert(index);
+                }
+                State::Gas => {}
+            }
+        }
+
+        self.wake(x, y);
+    }
+
+    /// Sets every cluster of rock touching a rock cell that was placed or removed falling,
+    /// unless the cluster is connected through rock to something that anchors it.
+    fn settle(&mut self) {
+        let mut unsettled = std::mem::take(&mut self.unsettled);
+
+        for &index in &unsettled {
+            let (x, y) = (index as isize % self.width, index as isize / self.width);
+
+            for &(dx, dy) in [(0, 0)].iter().chain(&ADJACENT) {
+                if !self.is_structure(x + dx, y + dy) {
+                    continue;
+                }
+
+                let start = self.index(x + dx, y + dy);
+
+                if self.visited.contains(start) {
+                    continue;
+                }
+
+                let cluster = self.explored.len();
+
+                if self.is_anchored(start) {
+                    for &index in &self.explored[cluster..] {
+                        self.anchored.insert(index);
+                    }
+                } else {
+                    let mut cells = self.explored[cluster..].to_vec();
+                    cells.sort_unstable();
+
+                    for &index in &cells {
+                        self.falling.insert(index);
+                    }
+
+                    self.bodies.push(Body { cells, drop: 0 });
+                }
+            }
+        }
+
+        for index in self.explored.drain(..) {
+            self.visited.remove(index);
+            self.anchored.remove(index);
+        }
+
+        unsettled.clear();
+        self.unsettled.append(&mut unsettled);
+    }
+
+    /// Searches the cluster of rock around the given cell, lowest cells first as rock usually
+    /// rests on something, until it finds an anchor or a cluster already found to be anchored.
+    /// The cells searched are left in `explored`.
+    fn is_anchored(&mut self, start: usize) -> bool {
+        self.visited.insert(start);
+        self.explored.push(start);
+        self.frontier.push(Reverse(start));
+
+        let mut anchored = false;
+
+        'search: while let Some(Reverse(index)) = self.frontier.pop() {
+            let (x, y) = (index as isize % self.width, index as isize / self.width);
+
+            for &(dx, dy) in &ADJACENT {
+                if self.anchors(x + dx, y + dy) {
+                    anchored = true;
+                    break 'search;
+                }
+
+                if !self.is_structure(x + dx, y + dy) {
+                    continue;
+                }
+
+                let next = self.index(x + dx, y + dy);
+
+                if self.anchored.contains(next) {
+                    anchored = true;
+                    break 'search;
+                }
+
+                if !self.visited.contains(next) {
+                    self.visited.insert(next);
+                    self.explored.push(next);
+                    self.frontier.push(Reverse(next));
+                }
+            }
+        }
+
+        self.frontier.clear();
+
+        anchored
+    }
+
+    /// Whether the given position holds rock that is part of the structure of the map, rather
+    /// than falling.
+    fn is_structure(&self, x: isize, y: isize) -> bool {
+        match self.locate(x, y) {
+            Some(index) => bears_load(self.cells[index]) && !self.falling.contains(index),
+            None => false,
+        }
+    }
+
+    /// Moves every falling body of rock down by one cell if nothing holds it up. Bodies that
+    /// land shatter into sand if they fell far enough, or else lie where they landed until they
+    /// can fall again, becoming part of the structure once they touch it.
+    fn fall(&mut self) {
+        let mut bodies = std::mem::take(&mut self.bodies);
+        let mut i = 0;
+
+        while i < bodies.len() {
+            if self.lower(&mut bodies[i]) {
+                i += 1;
+            } else {
+                bodies.swap_remove(i);
+            }
+        }
+
+        bodies.append(&mut self.bodies);
+        self.bodies = bodies;
+    }
+
+    /// Moves a single falling body, returning whether it still falls.
+    fn lower(&mut self, body: &mut Body) -> bool {
+        // Cells that were changed by anything else, such as acid or heat, are no longer part
+        // of the body, which may have been split up, so what is left of it is searched again.
+        let len = body.cells.len();
+        let falling = &self.falling;
+        body.cells.retain(|&index| falling.contains(index));
+
+        if body.cells.len() < len {
+            for &index in &body.cells {
+                self.falling.remove(index);
+                self.unsettled.push(index);
+            }
+
+            return false;
+        }
+
+        let width = self.width as usize;
+        let blocked = body.cells.iter().any(|&index| {
+            index < width
+                || body.cells.binary_search(&(index - width)).is_err()
+                    && (self.falling.contains(index - width)
+                        || !self.flows_into(self.cells[index], Some(self.cells[index - width])))
+        });
+
+        if !blocked {
+            // Cells are sorted bottom to top, so each one moves into the cell freed up by the
+            // one below it.
+            for index in body.cells.iter_mut() {
+                let (x, y) = (*index as isize % self.width, *index as isize / self.width);
+
+                self.swap(x, y, x, y - 1, self.cells[*index]);
+                *index -= width;
+                self.falling.insert(*index);
+            }
+
+            body.drop = body.drop.saturating_add(1);
+            return true;
+        }
+
+        if body.drop >= SHATTER_DROP {
+            for &index in &body.cells {
+                let index = index as isize;
+                self.write(index % self.width, index / self.width, Element::Sand(0));
+            }
+
+            return false;
+        }
+
+        body.drop = 0;
+
+        let lands = body.cells.iter().any(|&index| {
+            let (x, y) = (index as isize % self.width, index as isize / self.width);
+
+            ADJACENT
+                .iter()
+                .any(|&(dx, dy)| self.anchors(x + dx, y + dy) || self.is_structure(x + dx, y + dy))
+        });
+
+        if lands {
+            for &index in &body.cells {
+                self.falling.remove(index);
+                self.unsettled.push(index);
+            }
+
+            return false;
+        }
+
+        true
+    }
+
+    /// Evens out the temperature of the given cell with the ones next to it, turning any cell
+    /// whose temperature changed into another element if it got hot or cold enough.
+    fn conduct(&mut self, x: isize, y: isize) {
+        let index = self.index(x, y);
+        let mut changed = false;
+
+        for &(dx, dy) in &ADJACENT {
+            let neighbour = match self.locate(x + dx, y + dy) {
+                Some(neighbour) => neighbour,
+                None => continue,
+            };
+
+            // Conductivities are at most 1, so small differences are skipped before looking
+            // them up.
+            let difference = self.temperatures[neighbour] - self.temperatures[index];
+
+            if difference.abs() < HEAT_FLOW_THRESHOLD * 2.0 {
+                continue;
+            }
+
+            let (here, there) = (
+                self.elements.get(self.cells[index]),
+                self.elements.get(self.cells[neighbour]),
+            );
+            let flow = here.conductivity.min(there.conductivity) * difference / 2.0;
+
+            if flow.abs() < HEAT_FLOW_THRESHOLD || here.holds_temperature && there.holds_temperature
+            {
+                continue;
+            }
+
+            if !here.holds_temperature {
+                self.temperatures[index] += flow;
+            }
+
+            if !there.holds_temperature {
+                self.temperatures[neighbour] -= flow;
+            }
+
+            for &cell in &[index, neighbour] {
+                self.warm.insert(cell);
+                self.changed.insert(cell);
+            }
+
+            self.change_phase(x + dx, y + dy);
+            changed = true;
+        }
+
+        if changed {
+            self.change_phase(x, y);
+        }
+    }
+
+    /// Counts down the charge of the given cell, passing its spark on to the conductive cells
+    /// next to it and setting flammable ones around it alight while it carries one. Cells that
+    /// no longer conduct lose their charge.
+    fn discharge(&mut self, x: isize, y: isize) {
+        let index = self.index(x, y);
+        let charge = self.charges[index];
+
+        if charge == 0 {
+            return;
+        }
+
+        if !self.elements.get(self.cells[index]).conducts_electricity {
+            self.charges[index] = 0;
+            self.changed.insert(index);
+            return;
+        }
+
+        if charge > RECOVERY_TICKS {
+            self.spark(x, y);
+            self.ignite(x, y);
+        }
+
+        self.charges[index] = charge - 1;
+        self.changed.insert(index);
+
+        if charge > 1 {
+            self.charged.insert(index);
+        }
+    }
+
+    /// Sparks every conductive cell next to the given position that is ready to carry one.
+    fn spark(&mut self, x: isize, y: isize) {
+        for &(dx, dy) in &ADJACENT {
+            let neighbour = match self.locate(x + dx, y + dy) {
+                Some(neighbour) => neighbour,
+                None => continue,
+            };
+
+            if self.charges[neighbour] == 0
+                && self
+                    .elements
+                    .get(self.cells[neighbour])
+                    .conducts_electricity
+            {
+                self.charges[neighbour] = SPARK_TICKS + RECOVERY_TICKS;
+                self.charged.insert(neighbour);
+                self.changed.insert(neighbour);
+            }
+        }
+    }
+
+    fn heat(&mut self, x: isize, y: isize, temperature: f32) {
+        let index = self.index(x, y);
+
+        if self.temperatures[index] != temperature {
+            self.temperatures[index] = temperature;
+            self.warm.insert(index);
+            self.changed.insert(index);
+        }
+    }
+
+    fn change_phase(&mut self, x: isize, y: isize) {
+        let index = self.index(x, y);
+        let temperature = self.temperatures[index];
+        let phases = self.elements.phases(self.cells[index]);
+
+        match (phases.cools_into, phases.heats_into) {
+            (Some(cooled), _) if temperature < cooled.temperature - cooled.latent_heat => {
+                self.heat(x, y, temperature + cooled.latent_heat);
+                self.write(x, y, cooled.element);
+            }
+            (_, Some(heated)) if temperature > heated.temperature + heated.latent_heat => {
+                self.heat(x, y, temperature - heated.latent_heat);
+
+                match heated.element {
+                    Element::Fire(_) => self.burn(x, y),
+                    element => self.write(x, y, element),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies the first of the element's reactions that happens to one of its neighbours this
+    /// tick, staying awake while any of them could still happen. Returns whether one did.
+    fn react(
+        &mut self,
+        x: isize,
+        y: isize,
+        element: Element,
+        neighbours: [Option<Element>; 8],
+    ) -> bool {
+        for reaction in 0..self.elements.reactions(element).len() {
+            let reaction = self.elements.reactions(element)[reaction];
+
+            for (&(dx, dy), neighbour) in NEIGHBOURHOOD.iter().zip(neighbours) {
+                match neighbour {
+                    Some(other) if other.same_as(reaction.neighbour) => {}
+                    _ => continue,
+                }
+
+                if self.rng.gen::<f32>() < reaction.chance {
+                    self.write(x, y, reaction.into);
+                    self.write(x + dx, y + dy, reaction.neighbour_into);
+                    self.updated.insert(self.index(x + dx, y + dy));
+                    return true;
+                }
+
+                self.wake(x, y);
+            }
+        }
+
+        false
+    }
+
+    /// Sets each flammable neighbour of the given position on fire with a chance equal to its
+    /// flammability, staying awake while any of them is left unburnt.
+    fn ignite(&mut self, x: isize, y: isize) {
+        for &(dx, dy) in &NEIGHBOURHOOD {
+            let flammability = match self.get(x + dx, y + dy) {
+                Some(neighbour) => self.elements.get(neighbour).flammability,
+                None => continue,
+            };
+
+            if flammability <= 0.0 {
+                continue;
+            }
+
+            if self.rng.gen::<f32>() < flammability {
+                self.burn(x + dx, y + dy);
+            } else {
+                self.wake(x, y);
+            }
+        }
+    }
+
+    /// Sets the given position on fire, or blows it up if it is an explosive.
+    fn burn(&mut self, x: isize, y: isize) {
+        let blast_radius = self.elements.get(self.cells[self.index(x, y)]).blast_radius;
+
+        self.heat(x, y, self.initial_temperature(Element::Fire(0)));
+        self.write(x, y, Element::Fire(0));
+        self.updated.insert(self.index(x, y));
+
+        if blast_radius > 0 {
+            self.explode(x, y, blast_radius as isize);
+        }
+    }
+
+    /// Sets flammable cells within `radius` of the given position on fire, crumbles rock and
+    /// throws powders and liquids outwards, all the more likely and the faster the closer they
+    /// are. Gases close to the centre catch fire.
+    fn explode(&mut self, x: isize, y: isize, radius: isize) {
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let distance = ((dx * dx + dy * dy) as f32).sqrt();
+
+                if distance > radius as f32 || (dx, dy) == (0, 0) {
+                    continue;
+                }
+
+                let index = match self.locate(x + dx, y + dy) {
+                    Some(index) => index,
+                    None => continue,
+                };
+
+                let target = self.cells[index];
+                let power = 1.0 - distance / radius as f32;
+                let definition = self.elements.get(target);
+                let (state, flammability) = (definition.state, definition.flammability);
+
+                if flammability > 0.0 {
+                    self.heat(x + dx, y + dy, self.initial_temperature(Element::Fire(0)));
+                    self.write(x + dx, y + dy, Element::Fire(0));
+                } else if bears_load(target) {
+                    if self.rng.gen::<f32>() < power * 2.0 {
+                        self.write(x + dx, y + dy, Element::Sand(0));
+                    }
+                } else {
+                    match state {
+                        State::Powder | State::Liquid => {
+                            let speed = power * BLAST_SPEED / distance;
+
+                            self.velocities[index] = (dx as f32 * speed, dy as f32 * speed);
+                            self.awake.insert(index);
+                        }
+                        State::Gas if self.rng.gen::<f32>() < power => {
+                            self.heat(x + dx, y + dy, self.initial_temperature(Element::Fire(0)));
+                            self.write(x + dx, y + dy, Element::Fire(0));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Dissolves one of the solids or powders next to the acid at the given position into air,
+    /// with a chance that is lower the stronger they hold together. Returns the acid that is
+    /// left, which is neutralised into water once it used up its potency.
+    fn corrode(&mut self, x: isize, y: isize, element: Element, used: u8) -> Element {
+        let definition = self.elements.get(element);
+        let (corrosiveness, potency) = (definition.corrosiveness, definition.potency);
+
+        for &(dx, dy) in &ADJACENT {
+            let resistance = match self.get(x + dx, y + dy) {
+                Some(Element::Wall) => continue,
+                neighbour => match self.support_strength(neighbour) {
+                    0 => continue,
+                    resistance => resistance,
+                },
+            };
+
+            self.wake(x, y);
+
+            if self.rng.gen::<f32>() < corrosiveness / resistance as f32 {
+                self.write(x + dx, y + dy, Element::Air);
+
+                return match used.checked_add(1) {
+                    Some(used) if used < potency => Element::Acid(used),
+                    _ => Element::Water,
+                };
+            }
+        }
+
+        element
+    }
+
+    /// Age of an element with a finite lifetime after one more tick, or `None` once it has
+    /// expired. Elements without a lifetime never age.
+    fn grow_older(&self, element: Element, age: u8) -> Option<u8> {
+        let lifetime = self.elements.get(element).lifetime;
+
+        if lifetime == 0 {
+            Some(age)
+        } else if age.saturating_add(1) >= lifetime {
+            None
+        } else {
+            Some(age + 1)
+        }
+    }
+
+    /// Where a liquid moves to: up or down according to its density, otherwise up to its
+    /// dispersion sideways into anything lighter, unless its viscosity holds it back. Surfaces
+    /// that cannot spread sideways spill over to lower parts of the same body.
+    fn flow(&mut self, x: isize, y: isize, element: Element) -> (isize, isize) {
+        if self.sinks_into(element, self.get(x, y - 1)) {
+            return (x, y - 1);
+        }
+
+        if self.rises_into(element, self.get(x, y + 1)) {
+            return (x, y + 1);
+        }
+
+        let left = self.flows_into(element, self.get(x - 1, y));
+        let right = self.flows_into(element, self.get(x + 1, y));
+
+        if !left && !right {
+            return self.spill(x, y, element).unwrap_or((x, y));
+        }
+
+        self.wake(x, y);
+
+        let definition = self.elements.get(element);
+        let (viscosity, dispersion) = (definition.viscosity, definition.dispersion);
+
+        if viscosity > 0.0 && self.rng.gen::<f32>() < viscosity {
+            return (x, y);
+        }
+
+        let direction = destabilize_offset(&mut self.rng, left, right, 5.0);
+        let mut dest_x = x;
+
+        for _ in 0..dispersion.max(1) {
+            if direction == 0 || !self.flows_into(element, self.get(dest_x + direction, y)) {
+                break;
+            }
+
+            dest_x += direction;
+
+            if self.sinks_into(element, self.get(dest_x, y - 1)) {
+                break;
+            }
+        }
+
+        (dest_x, y)
+    }
+
+    /// Liquid pressure: searches the body of liquid below a surface cell for a place lower
+    /// than it that the liquid could flow into, so that connected bodies level out like
+    /// communicating vessels. A surface with nowhere to go sleeps until something around it
+    /// changes.
+    ///
+    /// The whole body is searched if need be, as the place may be on the far side of a
+    /// container as wide as the map. The nearest cells are searched first, as a place is
+    /// usually close by in a body that is still settling. Cells are visited bottom to
+    /// top, so a failed search spares the other surfaces of the body at or below it for the
+    /// rest of the tick.
+    fn spill(&mut self, x: isize, y: isize, element: Element) -> Option<(isize, isize)> {
+        if !self.flows_into(element, self.get(x, y + 1)) {
+            return None;
+        }
+
+        let start = self.index(x, y);
+
+        if self.stagnant.contains(start) {
+            return None;
+        }
+
+        if self.rng.gen_range(0..SPILL_INTERVAL) != 0 {
+            self.wake(x, y);
+            return None;
+        }
+
+        self.visited.insert(start);
+        self.explored.push(start);
+
+        let mut found = None;
+        let mut searched = 0;
+
+        // Cells are explored in the order they were found in, nearest first.
+        'search: while let Some(&index) = self.explored.get(searched) {
+            let (cell_x, cell_y) = (index as isize % self.width, index as isize / self.width);
+            searched += 1;
+
+            for &(dx, dy) in &ADJACENT {
+                let (next_x, next_y) = (cell_x + dx, cell_y + dy);
+                let next = match self.locate(next_x, next_y) {
+                    Some(next) => next,
+                    None => continue,
+                };
+
+                if self.visited.contains(next) {
+                    continue;
+                }
+
+                let neighbour = self.cells[next];
+
+                if neighbour.same_as(element) {
+                    self.visited.insert(next);
+                    self.explored.push(next);
+                } else if next_y < y && self.flows_into(element, Some(neighbour)) {
+                    found = Some((next_x, next_y));
+                    break 'search;
+                }
+            }
+        }
+
+        let width = self.width as usize;
+
+        for index in self.explored.drain(..) {
+            self.visited.remove(index);
+
+            if found.is_none() && index / width <= y as usize {
+                self.stagnant.insert(index);
+            }
+        }
+
+        found
+    }
+
+    /// Where a gas moves to: up or down according to its density, otherwise sideways into air.
+    fn float(&mut self, x: isize, y: isize, element: Element) -> (isize, isize) {
+        if self.rises_into(element, self.get(x, y + 1)) {
+            return (x, y + 1);
+        }
+
+        if self.sinks_into(element, self.get(x, y - 1)) {
+            return (x, y - 1);
+        }
+
+        let left = self.get(x - 1, y) == Some(Element::Air);
+        let right = self.get(x + 1, y) == Some(Element::Air);
+
+        if left || right {
+            self.wake(x, y);
+        }
+
+        (x + destabilize_offset(&mut self.rng, left, right, 5.0), y)
+    }
+
+    fn write(&mut self, x: isize, y: isize, element: Element) {
+        self.store(self.index(x, y), x, y, element);
+    }
+
+    /// Writes `element` to the cell at `index`, which is the given position.
+    fn store(&mut self, index: usize, x: isize, y: isize, element: Element) {
+        if self.cells[index] != element {
+            if bears_load(self.cells[index]) != bears_load(element) {
+                self.unsettled.push(index);
+            }
+
+            self.falling.remove(index);
+
+            self.cells[index] = element;
+            self.changed.insert(index);
+
+            self.wake_neighbourhood(index, x, y);
+        }
+    }
+
+    /// Finds where an unsupported powder slides to, keeping piles at the element's angle of
+    /// repose. Angles in between whole distances are reached on average by randomly rounding
+    /// them.
+    fn slide(&mut self, x: isize, y: isize, element: Element) -> Option<(isize, isize)> {
+        let repose = self.elements.repose(element);
+        let run = randomized_round(&mut self.rng, repose.run);
+        let drop = randomized_round(&mut self.rng, repose.drop);
+
+        let directions = if self.rng.gen() { [-1, 1] } else { [1, -1] };
+
+        directions
+            .iter()
+            .find_map(|&direction| self.landing(x, y, element, direction * run, drop))
+            .map(|dest_x| (dest_x, y - 1))
+    }
+
+    /// The first column within `run` cells of the given position that a grain can slide
+    /// sideways to and then drop by `drop`, as any that is closer makes for a steeper slope.
+    fn landing(
+        &self,
+        x: isize,
+        y: isize,
+        element: Element,
+        run: isize,
+        drop: isize,
+    ) -> Option<isize> {
+        let step = run.signum();
+
+        for i in 1..=run.abs() {
+            let dest_x = x + step * i;
+
+            if !self.flows_into(element, self.get(dest_x, y)) {
+                return None;
+            }
+
+            if (1..=drop).all(|depth| self.sinks_into(element, self.get(dest_x, y - depth))) {
+                return Some(dest_x);
+            }
+        }
+
+        None
+    }
+
+    /// Wakes the cell at `index`, which is the given position, and the eight surrounding it.
+    fn wake_neighbourhood(&mut self, index: usize, x: isize, y: isize) {
+        if x > 0 && y > 0 && x < self.width - 1 && y < self.height - 1 {
+            let width = self.width as usize;
+
+            for &row in &[index - width, index, index + width] {
+                self.awake.insert_range(row - 1..row + 2);
+            }
+
+            return;
+        }
+
+        self.wake(x, y);
+
+        for &(dx, dy) in &NEIGHBOURHOOD {
+            self.wake(x + dx, y + dy);
+        }
+    }
+
+    /// The elements around the given position, in the order of `NEIGHBOURHOOD`.
+    fn neighbourhood(&self, x: isize, y: isize) -> [Option<Element>; 8] {
+        if x > 0 && y > 0 && x < self.width - 1 && y < self.height - 1 {
+            // Away from the edges the neighbours cannot lie past one, so they are read straight
+            // from the grid, which is a lot quicker for the many cells evaluated every tick.
+            let width = self.width as usize;
+            let index = self.index(x, y);
+            let (above, below) = (index + width, index - width);
+
+            return [
+                Some(self.cells[above - 1]),
+                Some(self.cells[above]),
+                Some(self.cells[above + 1]),
+                Some(self.cells[index - 1]),
+                Some(self.cells[index + 1]),
+                Some(self.cells[below - 1]),
+                Some(self.cells[below]),
+                Some(self.cells[below + 1]),
+            ];
+        }
+
+        NEIGHBOURHOOD.map(|(dx, dy)| self.get(x + dx, y + dy))
+    }
+
+    fn wake(&mut self, x: isize, y: isize) {
+        if let Some(index) = self.locate(x, y) {
+            self.awake.insert(index);
+        }
+    }
+
+    /// Index of the cell at the given position, which has to be on the map once wrapped.
+    fn index(&self, x: isize, y: isize) -> usize {
+        self.locate(x, y)
+            .expect("position is past the edge of the map")
+    }
+
+    /// Index of the cell at the given position, wrapping around edges that wrap, or `None` if
+    /// it is past any other edge.
+    fn locate(&self, x: isize, y: isize) -> Option<usize> {
+        if self.contains(x, y) {
+            return Some((y * self.width + x) as usize);
+        }
+
+        self.wrap(x, y).map(|(x, y)| (y * self.width + x) as usize)
+    }
+
+    fn contains(&self, x: isize, y: isize) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height
+    }
+
+    /// Where the given position is once it is wrapped around the edges that wrap, or `None` if
+    /// it is past any other edge.
+    fn wrap(&self, x: isize, y: isize) -> Option<(isize, isize)> {
+        // Positions can lie several times the size of the map past an edge, such as those of
+        // wide explosions on small maps.
+        let wrap = |position: isize, size: isize, low: Border, high: Border| match position {
+            position if position < 0 => (low == Border::Wrap).then(|| position.rem_euclid(size)),
+            position if position >= size => {
+                (high == Border::Wrap).then(|| position.rem_euclid(size))
+            }
+            position => Some(position),
+        };
+
+        Some((
+            wrap(x, self.width, self.borders.left, self.borders.right)?,
+            wrap(y, self.height, self.borders.bottom, self.borders.top)?,
+        ))
+    }
+
+    /// What lies past the edge of the map at the given position, if it is outside the map.
+    fn border(&self, x: isize, y: isize) -> Option<Border> {
+        match (x, y) {
+            (x, _) if x < 0 => Some(self.borders.left),
+            (x, _) if x >= self.width => Some(self.borders.right),
+            (_, y) if y < 0 => Some(self.borders.bottom),
+            (_, y) if y >= self.height => Some(self.borders.top),
+            _ => None,
+        }
+    }
+
+    /// Whether rock next to the given position is held in place by it: walls and solid edges
+    /// hold rock up.
+    fn anchors(&self, x: isize, y: isize) -> bool {
+        match self.get(x, y) {
+            Some(element) => element == Element::Wall,
+            None => self.border(x, y) == Some(Border::Solid),
+        }
+    }
+
+    /// Whether the cell at the given position touches an edge that things fall out of.
+    fn falls_out(&self, x: isize, y: isize) -> bool {
+        if x > 0 && y > 0 && x < self.width - 1 && y < self.height - 1 {
+            return false;
+        }
+
+        ADJACENT
+            .iter()
+            .any(|&(dx, dy)| self.border(x + dx, y + dy) == Some(Border::Void))
+    }
+
+    fn initial_temperature(&self, element: Element) -> f32 {
+        self.elements
+            .get(element)
+            .temperature
+            .unwrap_or(AMBIENT_TEMPERATURE)
+    }
+
+    fn density(&self, element: Element) -> f32 {
+        self.elements.get(element).density
+    }
+
+    /// Whether `element` swaps places with `target` when it is directly below: any element that
+    /// is not solid sinks through lighter ones.
+    fn sinks_into(&self, element: Element, target: Option<Element>) -> bool {
+        match target {
+            Some(target) => {
+                let target = self.elements.get(target);
+                target.state != State::Solid && target.density < self.density(element)
+            }
+            None => false,
+        }
+    }
+
+    /// Whether `element` swaps places with `target` when it is directly above, so that lighter
+    /// fluids bubble up through heavier ones.
+    fn rises_into(&self, element: Element, target: Option<Element>) -> bool {
+        match target {
+            Some(target) => {
+                let target = self.elements.get(target);
+                is_fluid(target.state) && target.density > self.density(element)
+            }
+            None => false,
+        }
+    }
+
+    /// Whether `element` can spill sideways into `target`.
+    fn flows_into(&self, element: Element, target: Option<Element>) -> bool {
+        match target {
+            Some(target) => {
+                let target = self.elements.get(target);
+                is_fluid(target.state) && target.density < self.density(element)
+            }
+            None => false,
+        }
+    }
+
+    /// How strongly an element holds together, both to support powders above it and to resist
+    /// acid.
+    fn support_strength(&self, element: Option<Element>) -> u8 {
+        match element.map(|element| self.elements.get(element).state) {
+            Some(State::Powder) => 1,
+            Some(State::Solid) => 2,
+            _ => 0,
+        }
+    }
+}
+
+/// A cluster of rock that lost its support and falls as a whole.
+struct Body {
+    /// Positions of the cells, sorted bottom to top.
+    cells: Vec<usize>,
+    /// How many cells it has dropped without being held up.
+    drop: u8,
+}
+
+fn destabilize_offset(rng: &mut impl Rng, left: bool, right: bool, eagerness: f32) -> isize {
+    let min = if left { -eagerness } else { 0.0 };
+    let max = if right { eagerness } else { 0.0 };
+
+    (rng.gen_range(min..=max) as isize).signum()
+}
+
+/// Rounds up with a probability equal to the fractional part, so that the average over many
+/// calls equals `value`.
+fn randomized_round(rng: &mut impl Rng, value: f32) -> isize {
+    let whole = value.floor();
+
+    if rng.gen::<f32>() < value - whole {
+        whole as isize + 1
+    } else {
+        whole as isize
+    }
+}
+
+/// Rounds half away from zero like [`f32::round`], which is a call into the maths library on
+/// plain x86-64 and too slow for the paths of moving cells.
+fn round(value: f32) -> isize {
+    let whole = value as isize;
+    let fraction = value - whole as f32;
+
+    whole + (fraction >= 0.5) as isize - (fraction <= -0.5) as isize
+}
+
+/// Elements that hold each other in place, forming clusters that stay up as long as one of
+/// their cells is anchored.
+fn bears_load(element: Element) -> bool {
+    matches!(element, Element::Rock | Element::Obsidian)
+}
+
+fn is_fluid(state: State) -> bool {
+    matches!(state, State::Liquid | State::Gas)
+}
+
+/// Water that plants can drink, and that seeds sprout next to.
+fn is_moist(element: Option<Element>) -> bool {
+    matches!(element, Some(Element::Water | Element::WetSand(..)))
+}
+
+/// Powders other than dry and wet sand do not keep track of how well supported they are.
+fn with_support(element: Element, distance: u8) -> Element {
+    match element {
+        Element::Sand(_) => Element::Sand(distance),
+        Element::WetSand(_, age) => Element::WetSand(distance, age),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrapping_edges_wrap_positions_several_maps_away() {
+        let mut simulation = Simulation::new(16, 9, Element::Air, 0);
+        simulation.set_borders(Borders {
+            left: Border::Wrap,
+            ..Borders::default()
+        });
+
+        simulation.set(-26, 1, Element::Rock);
+
+        assert_eq!(simulation.get(6, 1), Some(Element::Rock));
+        assert_eq!(simulation.get(-42, 1), Some(Element::Rock));
+        assert_eq!(simulation.get(22, 1), None);
+    }
+
+    #[test]
+    fn deep_piles_do_not_overflow_their_support() {
+        let mut simulation = Simulation::new(5, 100, Element::Air, 0);
+
+        for y in 0..100 {
+            simulation.set(0, y, Element::Rock);
+            simulation.set(4, y, Element::Rock);
+        }
+
+        for (x, y) in (1..4).flat_map(|x| (0..80).map(move |y| (x, y))) {
+            simulation.set(x, y, Element::Sand(0));
+        }
+
+        for _ in 0..100 {
+            simulation.step();
+        }
+
+        let sand = simulation
+            .iter()
+            .filter(|&(x, y)| matches!(simulation.get(x, y), Some(Element::Sand(_))))
+            .count();
+
+        assert_eq!(sand, 3 * 80);
+    }
+
+    #[test]
+    fn water_in_a_u_shaped_rock_container_levels_out() {
+        let (width, height) = (160, 90);
+        let mut simulation = Simulation::new(width, height, Element::Air, 0);
+
+        // A U-shaped container carved out of rock: two 65 cell wide arms joined by a channel
+        // along the bottom, with only the left one filled. The rock between the arms hangs
+        // from the top edge of the map.
+        for (x, y) in simulation.iter().collect::<Vec<_>>() {
+            let channel = (2..157).contains(&x) && (3..8).contains(&y);
+            let arm = ((2..67).contains(&x) || (92..157).contains(&x)) && y >= 3;
+
+            if !channel && !arm && x < 159 {
+                simulation.set(x, y, Element::Rock);
+            } else if channel || arm && x < 67 && y < 60 {
+                simulation.set(x, y, Element::Water);
+            }
+        }
+
+        for _ in 0..3000 {
+            simulation.step();
+        }
+
+        let surface = |arm: std::ops::Range<isize>| {
+            simulation
+                .iter()
+                .filter(|&(x, y)| arm.contains(&x) && simulation.get(x, y) == Some(Element::Water))
+                .map(|(_, y)| y)
+                .max()
+                .unwrap()
+        };
+        let (left, right) = (surface(2..67), surface(92..157));
+
+        assert!((left - right).abs() <= 1, "left {} right {}", left, right);
+    }
+
+    #[test]
+    fn sparks_travel_through_wet_sand() {
+        let mut simulation = Simulation::new(12, 3, Element::Air, 0);
+        let battery = simulation.elements().find("Battery").unwrap();
+
+        simulation.set(0, 0, battery);
+
+        for x in 1..12 {
+            simulation.set(x, 0, Element::WetSand(0, 0));
+        }
+
+        let sparked = (0..20).any(|_| {
+            simulation.step();
+            simulation.is_sparking(11, 0)
+        });
+
+        assert!(sparked);
+    }
+
+    #[test]
+    fn reloaded_elements_keep_custom_cells() {
+        let source = include_str!("../assets/elements.ron")
+            .replacen(
+                "\n[\n",
+                "\n[\n(name: \"Dust\", colour: (0.3, 0.3, 0.3), density: 1.2, state: Powder),\n",
+                1,
+            )
+            .replace("name: \"Salt\"", "name: \"Rock Salt\"");
+        let elements = ElementTable::parse(source.as_bytes()).unwrap();
+
+        let mut simulation = Simulation::new(4, 4, Element::Air, 0);
+        let oil = simulation.elements().find("Oil").unwrap();
+        let salt = simulation.elements().find("Salt").unwrap();
+        simulation.set(1, 0, oil);
+        simulation.set(2, 0, salt);
+
+        simulation.set_elements(elements.clone());
+
+        assert_ne!(elements.find("Oil"), Some(oil));
+        assert_eq!(simulation.get(1, 0), elements.find("Oil"));
+        assert_eq!(simulation.get(2, 0), Some(Element::Air));
+    }
+
+    #[test]
+    fn same_seed_gives_same_grid() {
+        let run = |seed| {
+            let mut simulation = Simulation::new(40, 40, Element::Air, seed);
+
+            for (x, y) in simulation.iter().collect::<Vec<_>>() {
+                let element = match (x / 8 + y / 8) % 4 {
+                    0 => Element::Sand(0),
+                    1 => Element::Water,
+                    2 if y == 39 => Element::Fire(0),
+                    _ => continue,
+                };
+
+                simulation.set(x, y, element);
+            }
+
+            for _ in 0..200 {
+                simulation.step();
+            }
+
+            simulation
+                .iter()
+                .map(|(x, y)| (simulation.get(x, y), simulation.temperature(x, y)))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(7), run(7));
+    }
+
+    #[test]
+    fn heavier_elements_sink_through_lighter_ones() {
+        let mut simulation = Simulation::new(1, 6, Element::Air, 0);
+        let oil = simulation.elements().find("Oil").unwrap();
+        let gunpowder = simulation.elements().find("Gunpowder").unwrap();
+
+        for (y, element) in [oil, oil, Element::Water, Element::Water, gunpowder]
+            .iter()
+            .enumerate()
+        {
+            simulation.set(0, y as isize, *element);
+        }
+
+        for _ in 0..50 {
+            simulation.step();
+        }
+
+        let column = (0..6).map(|y| simulation.get(0, y)).collect::<Vec<_>>();
+
+        assert_eq!(column[0], Some(gunpowder));
+        assert_eq!(column[1..3], [Some(Element::Water); 2]);
+        assert_eq!(column[3..5], [Some(oil); 2]);
+        assert_eq!(column[5], Some(Element::Air));
+    }
+
+    #[test]
+    fn powders_pile_up_to_their_angle_of_repose() {
+        let (width, height) = (61, 40);
+        let mut simulation = Simulation::new(width, height, Element::Air, 0);
+
+        for (x, y) in (28..33).flat_map(|x| (10..40).map(move |y| (x, y))) {
+            simulation.set(x, y, Element::Sand(0));
+        }
+
+        for _ in 0..2000 {
+            simulation.step();
+        }
+
+        let heights = (0..width as isize)
+            .map(|x| {
+                (0..height as isize)
+                    .take_while(|&y| matches!(simulation.get(x, y), Some(Element::Sand(_))))
+                    .count() as isize
+            })
+            .collect::<Vec<_>>();
+
+        // Sand rests at 34°, so that its slopes drop by a cell every one or two cells.
+        let peak = *heights.iter().max().unwrap();
+        let base = heights.iter().filter(|&&height| height > 0).count() as isize;
+
+        assert_eq!(heights.iter().sum::<isize>(), 5 * 30);
+        assert!((8..=12).contains(&peak), "peak {}", peak);
+        assert!((2 * peak..=4 * peak).contains(&base), "base {}", base);
+        assert!(heights
+            .windows(2)
+            .all(|pair| (pair[0] - pair[1]).abs() <= 1));
+    }
+
+    #[test]
+    fn heat_changes_phases() {
+        let mut simulation = Simulation::new(3, 1, Element::Air, 0);
+        let ice = simulation.elements().find("Ice").unwrap();
+
+        simulation.set(0, 0, Element::Water);
+        simulation.set_temperature(0, 0, 150.0);
+        simulation.set(2, 0, Element::Water);
+        simulation.set_temperature(2, 0, -50.0);
+        simulation.step();
+
+        // The steam may have drifted sideways already.
+        assert!((0..2).any(|x| matches!(simulation.get(x, 0), Some(Element::Steam(_)))));
+        assert_eq!(simulation.get(2, 0), Some(ice));
+
+        // The latent heat is given back by the change.
+        assert!(simulation.temperature(2, 0).unwrap() > -50.0 + 19.0);
+    }
+
+    #[test]
+    fn neighbours_react() {
+        let mut simulation = Simulation::new(3, 3, Element::Air, 0);
+
+        simulation.set(1, 0, Element::Water);
+        simulation.set(1, 1, Element::Fire(0));
+        simulation.step();
+
+        let cells = simulation
+            .iter()
+            .filter_map(|(x, y)| simulation.get(x, y))
+            .filter(|&element| element != Element::Air)
+            .collect::<Vec<_>>();
+
+        assert_eq!(cells, [Element::Steam(0)]);
+    }
+
+    #[test]
+    fn rock_falls_once_nothing_holds_it_up() {
+        let mut simulation = Simulation::new(20, 20, Element::Air, 0);
+
+        // A floating block, a ledge hanging from a wall and a pillar standing on the floor.
+        for (x, y) in (2..5).flat_map(|x| (5..8).map(move |y| (x, y))) {
+            simulation.set(x, y, Element::Rock);
+        }
+
+        simulation.set(10, 10, Element::Wall);
+        simulation.set(11, 10, Element::Rock);
+        simulation.set(12, 10, Element::Rock);
+
+        for y in 0..6 {
+            simulation.set(16, y, Element::Rock);
+        }
+
+        for _ in 0..10 {
+            simulation.step();
+        }
+
+        assert!((2..5).all(|x| (0..3).all(|y| simulation.get(x, y) == Some(Element::Rock))));
+        assert_eq!(simulation.get(3, 5), Some(Element::Air));
+        assert_eq!(simulation.get(12, 10), Some(Element::Rock));
+        assert_eq!(simulation.get(16, 5), Some(Element::Rock));
+
+        // Cutting the pillar off the floor lets the rest of it fall.
+        simulation.set(16, 0, Element::Air);
+
+        for _ in 0..10 {
+            simulation.step();
+        }
+
+        assert!((0..5).all(|y| simulation.get(16, y) == Some(Element::Rock)));
+        assert_eq!(simulation.get(16, 5), Some(Element::Air));
+    }
+
+}
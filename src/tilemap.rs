@@ -1,5 +1,4 @@
 use bevy::{
-    ecs::{component::Component, system::EntityCommands},
     prelude::*,
     reflect::TypeUuid,
     render::{
@@ -13,30 +12,26 @@ use bevy::{
     },
 };
 
-use crate::GameStage;
+use crate::{bitset::BitSet, GameStage};
 
-pub struct TilemapPlugin<Tile> {
+pub struct TilemapPlugin {
     scale: f32,
     width: isize,
     height: isize,
-    template: Tile,
 }
 
-impl<Tile> TilemapPlugin<Tile> {
-    pub fn new(width: usize, height: usize, scale: f32, template: Tile) -> Self {
+impl TilemapPlugin {
+    pub fn new(width: usize, height: usize, scale: f32) -> Self {
         Self {
             width: width as isize,
             height: height as isize,
             scale,
-            template,
         }
     }
 }
 
-impl<Tile: Component + Copy> Plugin for TilemapPlugin<Tile> {
+impl Plugin for TilemapPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        let template = self.template;
-
         let surface = {
             let mut texture = Texture::new_fill(
                 Extent3d {
@@ -45,8 +40,8 @@ impl<Tile: Component + Copy> Plugin for TilemapPlugin<Tile> {
                     depth: 32,
                 },
                 TextureDimension::D2,
-                &[0],
-                TextureFormat::R8Uint,
+                &[0, 0, 0, 0],
+                TextureFormat::Rgba8Uint,
             );
 
             texture.sampler.set_address_mode(AddressMode::ClampToEdge);
@@ -61,11 +56,9 @@ impl<Tile: Component + Copy> Plugin for TilemapPlugin<Tile> {
             scale: self.scale,
             width: self.width,
             height: self.height,
-            content: vec![Entity::new(0); self.width as usize * self.height as usize]
+            content: vec![Material::default(); self.width as usize * self.height as usize]
                 .into_boxed_slice(),
-            initializer: Box::new(move |commands| {
-                commands.insert(template);
-            }),
+            dirty: BitSet::new(self.width as usize * self.height as usize),
             surface,
         });
 
@@ -73,8 +66,6 @@ impl<Tile: Component + Copy> Plugin for TilemapPlugin<Tile> {
 
         app.add_startup_system(init.system());
 
-        app.add_system_to_stage(GameStage::Tally, neighbours::<Tile>.system());
-
         app.add_system_to_stage(GameStage::Tally, sync_surface.system());
     }
 }
@@ -83,8 +74,8 @@ pub struct Tilemap {
     scale: f32,
     width: isize,
     height: isize,
-    content: Box<[Entity]>,
-    initializer: Box<dyn Fn(&mut EntityCommands) + Send + Sync>,
+    content: Box<[Material]>,
+    dirty: BitSet,
     surface: Handle<Texture>,
 }
 
@@ -94,23 +85,17 @@ impl Tilemap {
         (x.round() as isize, y.round() as isize)
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (isize, isize)> {
-        let width = self.width;
-        let height = self.height;
-
-        (0..height).flat_map(move |y| (0..width).map(move |x| (x, y)))
-    }
-
-    pub fn get(&self, x: isize, y: isize) -> Option<Entity> {
-        if x < 0 || y < 0 || x >= self.width || y >= self.width {
-            return None;
+    pub fn set(&mut self, x: isize, y: isize, material: Material) {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return;
         }
 
-        self.content.get(self.index(x, y)).cloned()
-    }
+        let index = self.index(x, y);
 
-    fn get_mut(&mut self, x: isize, y: isize) -> &mut Entity {
-        &mut self.content[self.index(x, y)]
+        if self.content[index] != material {
+            self.content[index] = material;
+            self.dirty.insert(index);
+        }
     }
 
     fn index(&self, x: isize, y: isize) -> usize {
@@ -118,23 +103,35 @@ impl Tilemap {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct TilePosition(pub isize, pub isize);
-
-#[derive(Debug)]
-pub struct LeftNeighbour<T: Component>(pub T);
-
-#[derive(Debug)]
-pub struct RightNeighbour<T: Component>(pub T);
-
-#[derive(Debug)]
-pub struct UpNeighbour<T: Component>(pub T);
+/// What a single tile looks like: its base colour and the shading `tilemap.frag` applies to it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Material {
+    pub colour: [u8; 3],
+    pub look: Look,
+}
 
-#[derive(Debug)]
-pub struct DownNeighbour<T: Component>(pub T);
+impl Material {
+    /// Shows a temperature, in °C, instead of an element. The shader colours it from the red
+    /// channel, which holds the temperature in steps of 5 °C starting at -50 °C.
+    pub fn heat(temperature: f32) -> Self {
+        Self {
+            colour: [((temperature + 50.0) / 5.0).clamp(0.0, 255.0) as u8, 0, 0],
+            look: Look::Heat,
+        }
+    }
+}
 
-#[derive(Debug)]
-pub struct Material(pub u8);
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Look {
+    #[default]
+    Empty = 0,
+    Solid = 1,
+    Liquid = 2,
+    Powder = 3,
+    Gas = 4,
+    Heat = 5,
+    Spark = 6,
+}
 
 #[derive(RenderResources, TypeUuid)]
 #[uuid = "fe4aadbc-34d5-438f-8607-c92f5d856445"]
@@ -180,63 +177,9 @@ impl FromWorld for TilemapContext {
     }
 }
 
-fn neighbours<Tile: Component + Copy>(
-    mut commands: Commands,
-    tilemap: Res<Tilemap>,
-    tiles: Query<(&TilePosition, &Tile), Changed<Tile>>,
-) {
-    for (&TilePosition(x, y), target) in tiles.iter() {
-        mark_neighbour(
-            &mut commands,
-            &tilemap,
-            target,
-            x,
-            y - 1,
-            UpNeighbour::<Tile>,
-        );
-        mark_neighbour(
-            &mut commands,
-            &tilemap,
-            target,
-            x,
-            y + 1,
-            DownNeighbour::<Tile>,
-        );
-        mark_neighbour(
-            &mut commands,
-            &tilemap,
-            target,
-            x + 1,
-            y,
-            LeftNeighbour::<Tile>,
-        );
-        mark_neighbour(
-            &mut commands,
-            &tilemap,
-            target,
-            x - 1,
-            y,
-            RightNeighbour::<Tile>,
-        );
-    }
-}
-
-fn mark_neighbour<T: Component + Copy, C: Component>(
-    commands: &mut Commands,
-    tilemap: &Tilemap,
-    tile: &T,
-    x: isize,
-    y: isize,
-    constructor: impl Fn(T) -> C,
-) {
-    if let Some(entity) = tilemap.get(x, y) {
-        commands.entity(entity).insert(constructor(*tile));
-    }
-}
-
 fn init(
     mut commands: Commands,
-    mut tilemap: ResMut<Tilemap>,
+    tilemap: Res<Tilemap>,
     mut colors: ResMut<Assets<ColorMaterial>>,
     context: Res<TilemapContext>,
 ) {
@@ -246,34 +189,32 @@ fn init(
         sprite: Sprite::new(Vec2::new(tilemap.width as f32, tilemap.height as f32) * tilemap.scale),
         ..Default::default()
     });
-
-    for (x, y) in tilemap.iter() {
-        let mut builder = commands.spawn();
-
-        builder.insert(TilePosition(x, y));
-        builder.insert(Material(0));
-
-        (tilemap.initializer)(&mut builder);
-
-        *tilemap.get_mut(x, y) = builder.id();
-    }
 }
 
 fn sync_surface(
     time: Res<Time>,
-    tilemap: Res<Tilemap>,
+    mut tilemap: ResMut<Tilemap>,
     mut context: ResMut<TilemapContext>,
     mut textures: ResMut<Assets<Texture>>,
-    pixels: Query<(&TilePosition, &Material), Changed<Material>>,
 ) {
-    let surface = textures.get_mut(tilemap.surface.clone()).unwrap();
+    if !tilemap.dirty.is_empty() {
+        let surface = textures.get_mut(tilemap.surface.clone()).unwrap();
+
+        let width = tilemap.width as usize;
+        let height = tilemap.height as usize;
 
-    let width = tilemap.width as usize;
-    let height = tilemap.height as usize;
+        for index in tilemap.dirty.iter() {
+            let (x, y) = (index % width, index / width);
+            let start = ((height - y) * width + x) * 4;
+            let Material {
+                colour: [r, g, b],
+                look,
+            } = tilemap.content[index];
+
+            surface.data[start..start + 4].copy_from_slice(&[r, g, b, look as u8]);
+        }
 
-    for (&TilePosition(x, y), material) in pixels.iter() {
-        let start = (height - y as usize) * width + x as usize;
-        surface.data[start] = material.0;
+        tilemap.dirty.clear();
     }
 
     context.time = time.seconds_since_startup() as f32;